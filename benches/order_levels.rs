@@ -0,0 +1,45 @@
+//! Compares the tree and array price level backends on a dense price range
+//!
+//! Run with `cargo bench --bench order_levels`
+
+use std::{hint::black_box, time::Instant};
+
+use order_matching_engine::orderbook::{
+    orderlevels::{AskOrderLevels, OrderLevels},
+    PriceRange,
+};
+use uuid::Uuid;
+
+const MAX_PRICE: i64 = 999;
+const ORDERS: usize = 100_000;
+const ROUNDS: usize = 10;
+
+/// Inserts orders across the range, reads the best price after each,
+/// then removes them all, returning the nanoseconds taken per order
+fn bench_levels(mut new_levels: impl FnMut() -> AskOrderLevels) -> f64 {
+    let orders: Vec<(i64, Uuid)> = (0..ORDERS)
+        .map(|index| ((index * 7919) as i64 % (MAX_PRICE + 1), Uuid::new_v4()))
+        .collect();
+
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        let mut levels = new_levels();
+        for (price, order_id) in &orders {
+            levels.insert_order(*price, *order_id).unwrap();
+            black_box(levels.get_best_price());
+        }
+        for (price, order_id) in &orders {
+            black_box(levels.remove_order(price, order_id));
+        }
+    }
+    start.elapsed().as_nanos() as f64 / (ORDERS * ROUNDS) as f64
+}
+
+fn main() {
+    let tree_nanos = bench_levels(AskOrderLevels::new);
+    let price_range = PriceRange::new(0, MAX_PRICE, 1).unwrap();
+    let array_nanos = bench_levels(|| AskOrderLevels::with_price_range(price_range));
+
+    println!("tree levels:  {:.1} ns per order", tree_nanos);
+    println!("array levels: {:.1} ns per order", array_nanos);
+}
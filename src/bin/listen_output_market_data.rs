@@ -1,7 +1,8 @@
 use anyhow::Result;
 use borsh::BorshDeserialize;
 use order_matching_engine::market_data_outbox::market_data_outbox_worker::{
-    MULTICAST_ADDR, MULTICAST_PORT,
+    SequencedMarketDataUpdate, DEFAULT_HEARTBEAT_INTERVAL, MAX_DATAGRAM_BYTES, MULTICAST_ADDR,
+    MULTICAST_PORT,
 };
 use order_matching_engine::orderbook::MarketDataUpdate;
 use socket2::{Domain, Protocol, Socket, Type};
@@ -9,7 +10,8 @@ use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::sync::Arc;
 use tokio::net::UdpSocket;
 
-const BUFFER_SIZE: usize = 1024;
+/// Heartbeats missed before the publisher is reported as stale
+const MISSED_HEARTBEATS_BEFORE_STALE: u32 = 3;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -24,18 +26,59 @@ async fn main() -> Result<()> {
     let socket = Arc::new(socket);
 
     // Buffer to receive data
-    let mut buf = vec![0u8; BUFFER_SIZE];
+    let mut buf = vec![0u8; MAX_DATAGRAM_BYTES];
+
+    // Sequence of the last update received, to detect dropped packets
+    let mut last_sequence: Option<u64> = None;
 
     // Main receive loop
     println!("Waiting for trade updates...");
+    let stale_after = DEFAULT_HEARTBEAT_INTERVAL * MISSED_HEARTBEATS_BEFORE_STALE;
     loop {
-        let (size, _src_addr) = socket.recv_from(&mut buf).await?;
+        // Any packet, heartbeat or update, shows the publisher is alive
+        let Ok(received) = tokio::time::timeout(stale_after, socket.recv_from(&mut buf)).await
+        else {
+            eprintln!(
+                "No market data or heartbeat for {:?}, publisher may be down",
+                stale_after
+            );
+            continue;
+        };
+        let (size, _src_addr) = received?;
 
         // Try to deserialize the received data
-        match MarketDataUpdate::try_from_slice(&buf[..size]) {
-            Ok(trade) => {
-                println!("Received trade: {:#?}", trade);
-                println!("---------------------------------------------------");
+        match SequencedMarketDataUpdate::try_from_slice(&buf[..size]) {
+            Ok(SequencedMarketDataUpdate {
+                update:
+                    MarketDataUpdate::Heartbeat {
+                        last_sequence: heartbeat_sequence,
+                        ..
+                    },
+                ..
+            }) => {
+                // Heartbeats repeat the last sequence sent rather than taking a new one
+                if let Some(last_sequence) = last_sequence {
+                    if last_sequence != heartbeat_sequence {
+                        eprintln!(
+                            "Market data gap: last received sequence {}, heartbeat reports {}",
+                            last_sequence, heartbeat_sequence
+                        );
+                    }
+                }
+                last_sequence = Some(heartbeat_sequence);
+            }
+            Ok(SequencedMarketDataUpdate { sequence, update }) => {
+                warn_on_sequence_gap(last_sequence, sequence);
+                last_sequence = Some(sequence);
+
+                match update {
+                    MarketDataUpdate::Batch(updates) => {
+                        for update in updates {
+                            print_market_data_update(&update);
+                        }
+                    }
+                    update => print_market_data_update(&update),
+                }
             }
             Err(e) => {
                 eprintln!("Error deserializing trade data: {}", e);
@@ -44,6 +87,23 @@ async fn main() -> Result<()> {
     }
 }
 
+fn warn_on_sequence_gap(last_sequence: Option<u64>, sequence: u64) {
+    if let Some(last_sequence) = last_sequence {
+        if sequence != last_sequence + 1 {
+            eprintln!(
+                "Market data gap: expected sequence {}, received {}",
+                last_sequence + 1,
+                sequence
+            );
+        }
+    }
+}
+
+fn print_market_data_update(update: &MarketDataUpdate) {
+    println!("Received trade: {:#?}", update);
+    println!("---------------------------------------------------");
+}
+
 fn setup_multicast_socket() -> Result<UdpSocket> {
     // Create a socket
     let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
@@ -1,16 +1,107 @@
-use goose::prelude::*;
+use std::{env, f64::consts::PI, process, sync::OnceLock};
+
+use goose::{config::GooseConfiguration, prelude::*};
+use gumdrop::Options;
 use order_matching_engine::orderbook::{OrderSide, OrderType};
 use uuid::Uuid;
 
+const USAGE: &str = "Order distribution options, passed alongside goose's:
+  --price-mean PRICE        Mean order price (default: 100)
+  --price-std-dev PRICE     Standard deviation of order prices (default: 5)
+  --quantity-mean QUANTITY  Mean order quantity (default: 5)
+  --buy-probability P       Probability an order is a buy, between 0 and 1 (default: 0.5)";
+
+/// Set once from the command line before the attack starts
+static ORDER_DISTRIBUTION: OnceLock<OrderDistribution> = OnceLock::new();
+
+/// Shape of generated orders
+#[derive(Debug, PartialEq)]
+struct OrderDistribution {
+    price_mean: f64,
+    price_std_dev: f64,
+    quantity_mean: f64,
+    buy_probability: f64,
+}
+
+impl Default for OrderDistribution {
+    fn default() -> Self {
+        Self {
+            price_mean: 100.0,
+            price_std_dev: 5.0,
+            quantity_mean: 5.0,
+            buy_probability: 0.5,
+        }
+    }
+}
+
+impl OrderDistribution {
+    /// Takes the distribution options out of args, leaving goose's options
+    fn from_args(args: &mut Vec<String>) -> Result<Self, String> {
+        let mut order_distribution = Self::default();
+        let mut goose_args = vec![];
+
+        let mut args_iter = args.drain(..);
+        while let Some(arg) = args_iter.next() {
+            let option = match arg.as_str() {
+                "--price-mean" => &mut order_distribution.price_mean,
+                "--price-std-dev" => &mut order_distribution.price_std_dev,
+                "--quantity-mean" => &mut order_distribution.quantity_mean,
+                "--buy-probability" => &mut order_distribution.buy_probability,
+                _ => {
+                    goose_args.push(arg);
+                    continue;
+                }
+            };
+            let value = args_iter
+                .next()
+                .ok_or_else(|| format!("Missing value for {}", arg))?;
+            *option = value
+                .parse()
+                .map_err(|_| format!("Invalid value for {}: {}", arg, value))?;
+        }
+        drop(args_iter);
+
+        if !(0.0..=1.0).contains(&order_distribution.buy_probability) {
+            return Err(format!(
+                "--buy-probability must be between 0 and 1, got {}",
+                order_distribution.buy_probability
+            ));
+        }
+
+        *args = goose_args;
+        Ok(order_distribution)
+    }
+
+    /// Normally distributed price, never below 1
+    fn sample_price(&self, u1: f64, u2: f64) -> i64 {
+        let price = sample_normal(self.price_mean, self.price_std_dev, u1, u2);
+        (price.round() as i64).max(1)
+    }
+
+    /// Exponentially distributed quantity, mostly small with a tail of large orders
+    fn sample_quantity(&self, u: f64) -> u64 {
+        let quantity = -self.quantity_mean * (1.0 - u).ln();
+        (quantity.ceil() as u64).max(1)
+    }
+}
+
+/// Box-Muller transform of two uniform samples in [0, 1)
+fn sample_normal(mean: f64, std_dev: f64, u1: f64, u2: f64) -> f64 {
+    let radius = (-2.0 * (1.0 - u1).ln()).sqrt();
+    mean + std_dev * radius * (2.0 * PI * u2).cos()
+}
+
 async fn add_order(user: &mut GooseUser) -> TransactionResult {
-    let order_side = if rand::random_bool(0.5) {
+    let order_distribution = ORDER_DISTRIBUTION.get_or_init(OrderDistribution::default);
+
+    let order_side = if rand::random_bool(order_distribution.buy_probability) {
         OrderSide::Buy
     } else {
         OrderSide::Sell
     };
 
-    let price = rand::random_range(1..10);
-    let quantity = rand::random_range(1..10);
+    let price = order_distribution.sample_price(rand::random(), rand::random());
+    let quantity = order_distribution.sample_quantity(rand::random());
 
     let body = &serde_json::json!({
         "id": Uuid::new_v4(),
@@ -28,10 +119,100 @@ async fn add_order(user: &mut GooseUser) -> TransactionResult {
 
 #[tokio::main]
 async fn main() -> Result<(), GooseError> {
-    GooseAttack::initialize()?
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let order_distribution = OrderDistribution::from_args(&mut args).unwrap_or_else(|error| {
+        eprintln!("{}\n\n{}", error, USAGE);
+        process::exit(2);
+    });
+    let _ = ORDER_DISTRIBUTION.set(order_distribution);
+
+    let configuration = match GooseConfiguration::parse_args_default(&args) {
+        Ok(configuration) if configuration.help_requested() => {
+            println!("{}\n\n{}", USAGE, GooseConfiguration::usage());
+            process::exit(0);
+        }
+        Ok(configuration) => configuration,
+        Err(error) => {
+            eprintln!("{}\n\n{}", error, USAGE);
+            process::exit(2);
+        }
+    };
+
+    GooseAttack::initialize_with_config(configuration)?
         .register_scenario(scenario!("APIUser").register_transaction(transaction!(add_order)))
         .execute()
         .await?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn normal_sample_spread_by_std_dev() {
+        // u1 giving a radius of exactly one
+        let u1 = 1.0 - (-0.5_f64).exp();
+
+        assert_eq!(sample_normal(100.0, 5.0, 0.0, 0.3), 100.0);
+        assert!((sample_normal(100.0, 5.0, u1, 0.0) - 105.0).abs() < 1e-9);
+        assert!((sample_normal(100.0, 5.0, u1, 0.5) - 95.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn sampled_orders_are_valid() {
+        let order_distribution = OrderDistribution {
+            price_mean: 0.0,
+            ..Default::default()
+        };
+
+        assert_eq!(order_distribution.sample_price(0.0, 0.0), 1);
+        assert_eq!(order_distribution.sample_quantity(0.0), 1);
+        assert!(order_distribution.sample_quantity(0.99) > 20);
+    }
+
+    #[test]
+    fn distribution_options_taken_from_args() {
+        let mut goose_args = args(&[
+            "--host",
+            "http://localhost:8080",
+            "--buy-probability",
+            "0.7",
+            "--price-mean",
+            "50",
+            "-u",
+            "10",
+        ]);
+
+        let order_distribution = OrderDistribution::from_args(&mut goose_args).unwrap();
+
+        assert_eq!(
+            order_distribution,
+            OrderDistribution {
+                price_mean: 50.0,
+                buy_probability: 0.7,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            goose_args,
+            args(&["--host", "http://localhost:8080", "-u", "10"])
+        );
+    }
+
+    #[test]
+    fn buy_probability_outside_unit_interval_rejected() {
+        for buy_probability in ["-0.1", "1.5", "NaN"] {
+            let mut goose_args = args(&["--buy-probability", buy_probability]);
+
+            assert!(OrderDistribution::from_args(&mut goose_args).is_err());
+        }
+        assert!(OrderDistribution::from_args(&mut args(&["--price-mean"])).is_err());
+        assert!(OrderDistribution::from_args(&mut args(&["--price-mean", "abc"])).is_err());
+    }
+}
@@ -1,29 +1,68 @@
 use std::thread;
 
 use actix_web::{web, App, HttpServer};
+use chrono::Duration;
 use crossbeam::channel::{self, Receiver, Sender};
+#[cfg(feature = "parquet")]
+use order_matching_engine::parquet_export::parquet_exporter::{
+    ParquetExporter, DEFAULT_EXPORT_INTERVAL,
+};
 use order_matching_engine::{
     expiration_handler::expiration_handler::ExpirationHandler,
     market_data_outbox::market_data_outbox_worker::MarketDataWorker,
-    metrics::register_custom_metrics,
+    metrics::{register_custom_metrics, ORDER_BACKLOG},
+    order_engine::order_engine_worker::OrderEngineWorker,
     orderbook::{orderbook::Orderbook, MarketDataUpdate},
+    text_protocol::text_protocol_server::TextProtocolServer,
     web_server::{
         endpoints::{
-            cancel_order_endpoint, cancel_order_expiration_endpoint, create_order_endpoint,
-            metrics_endpoint, modify_order_endpoint,
+            admin_book_endpoint, backlog_endpoint, book_histogram_endpoint, cancel_level_endpoint,
+            cancel_order_endpoint, cancel_order_expiration_endpoint, cancel_orders_endpoint,
+            config_endpoint, create_oco_order_endpoint, create_order_endpoint, depth_endpoint,
+            expire_order_endpoint, json_config, metrics_endpoint, modify_order_endpoint,
+            order_status_endpoint, quote_endpoint, recent_rejections_endpoint,
+            recent_trades_endpoint, replace_order_endpoint, upsert_order_endpoint,
         },
         AppState, OrderRequest,
     },
 };
 
-fn worker_thread(receiver: Receiver<OrderRequest>, _market_data_sender: Sender<MarketDataUpdate>) {
-    let mut orderbook = Orderbook::new(None);
+const MAX_ORDER_AGE_SECONDS: i64 = 5;
+const ORDER_BACKLOG_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+/// Process cancels ahead of queued orders on a separate channel
+const PRIORITISE_CANCELS: bool = false;
+/// Environment variable holding the admin endpoint key, admin endpoints are disabled without it
+const ADMIN_KEY_VAR: &str = "ADMIN_KEY";
+/// Address to serve the text protocol on, disabled when None
+const TEXT_PROTOCOL_ADDRESS: Option<&str> = None;
+/// CPU core to pin the matching worker to, unpinned when None
+#[cfg(feature = "core_affinity")]
+const MATCHING_CORE_ID: Option<usize> = None;
+/// Directory the matched trades are exported to as Parquet files
+#[cfg(feature = "parquet")]
+const PARQUET_OUTPUT_DIR: &str = "trade_exports";
 
-    loop {
-        if let Ok(order_request) = receiver.recv() {
-            let _ = orderbook.place_trade_request(order_request);
+fn worker_thread(
+    receiver: Receiver<OrderRequest>,
+    cancel_receiver: Option<Receiver<OrderRequest>>,
+    _market_data_sender: Sender<MarketDataUpdate>,
+    orderbook_market_data_sender: Option<Sender<MarketDataUpdate>>,
+) {
+    #[cfg(feature = "core_affinity")]
+    if let Some(core_id) = MATCHING_CORE_ID {
+        if !order_matching_engine::order_engine::order_engine_worker::pin_current_thread(core_id) {
+            eprintln!("Failed to pin matching worker to core {}", core_id);
         }
     }
+
+    let orderbook = Orderbook::new(orderbook_market_data_sender);
+    let mut order_engine_worker = match cancel_receiver {
+        Some(cancel_receiver) => {
+            OrderEngineWorker::with_priority_cancels(receiver, cancel_receiver, orderbook)
+        }
+        None => OrderEngineWorker::new(receiver, orderbook),
+    };
+    order_engine_worker.run();
 }
 
 #[actix_web::main]
@@ -33,7 +72,14 @@ async fn main() -> std::io::Result<()> {
     let (order_engine_sender, order_engine_receiver) = channel::unbounded();
     let (order_expiration_sender, order_expiration_receiver) = channel::unbounded();
     let (market_data_sender, market_data_reciever) = channel::unbounded();
-    let cancellation_request_sender = order_engine_sender.clone();
+    let (cancel_request_sender, cancel_request_receiver) = match PRIORITISE_CANCELS {
+        true => {
+            let (cancel_request_sender, cancel_request_receiver) = channel::unbounded();
+            (cancel_request_sender, Some(cancel_request_receiver))
+        }
+        false => (order_engine_sender.clone(), None),
+    };
+    let cancellation_request_sender = cancel_request_sender.clone();
 
     thread::spawn(async move || {
         let mut market_data_worker = MarketDataWorker::new(market_data_reciever);
@@ -46,23 +92,79 @@ async fn main() -> std::io::Result<()> {
         expiration_handler.run();
     });
 
+    if let Some(text_protocol_address) = TEXT_PROTOCOL_ADDRESS {
+        let text_protocol_server = TextProtocolServer::new(order_engine_sender.clone());
+        thread::spawn(move || {
+            if let Err(error) = text_protocol_server.run(text_protocol_address) {
+                eprintln!("Text protocol server stopped: {}", error);
+            }
+        });
+    }
+
+    #[cfg(feature = "parquet")]
+    let orderbook_market_data_sender = {
+        std::fs::create_dir_all(PARQUET_OUTPUT_DIR)?;
+        let (parquet_sender, parquet_receiver) = channel::unbounded();
+        thread::spawn(move || {
+            let mut parquet_exporter = ParquetExporter::new(
+                parquet_receiver,
+                PARQUET_OUTPUT_DIR,
+                DEFAULT_EXPORT_INTERVAL,
+            );
+            parquet_exporter.run();
+        });
+        Some(parquet_sender)
+    };
+    #[cfg(not(feature = "parquet"))]
+    let orderbook_market_data_sender = None;
+
+    let backlog_sender = order_engine_sender.clone();
+    thread::spawn(move || loop {
+        ORDER_BACKLOG.set(backlog_sender.len() as i64);
+        thread::sleep(ORDER_BACKLOG_INTERVAL);
+    });
+
     thread::spawn(move || {
-        worker_thread(order_engine_receiver, market_data_sender);
+        worker_thread(
+            order_engine_receiver,
+            cancel_request_receiver,
+            market_data_sender,
+            orderbook_market_data_sender,
+        );
     });
 
     let state = web::Data::new(AppState {
         order_engine_sender,
+        cancel_request_sender,
         order_expiration_sender,
+        max_order_age: Duration::seconds(MAX_ORDER_AGE_SECONDS),
+        admin_key: std::env::var(ADMIN_KEY_VAR).ok(),
     });
 
     HttpServer::new(move || {
         App::new()
             .app_data(state.clone())
+            .app_data(json_config())
             .service(metrics_endpoint)
             .service(create_order_endpoint)
+            .service(create_oco_order_endpoint)
             .service(cancel_order_endpoint)
+            .service(cancel_orders_endpoint)
+            .service(cancel_level_endpoint)
             .service(modify_order_endpoint)
             .service(cancel_order_expiration_endpoint)
+            .service(expire_order_endpoint)
+            .service(recent_trades_endpoint)
+            .service(recent_rejections_endpoint)
+            .service(upsert_order_endpoint)
+            .service(replace_order_endpoint)
+            .service(quote_endpoint)
+            .service(config_endpoint)
+            .service(backlog_endpoint)
+            .service(admin_book_endpoint)
+            .service(book_histogram_endpoint)
+            .service(depth_endpoint)
+            .service(order_status_endpoint)
     })
     .bind(("127.0.0.1", 8080))?
     .run()
@@ -1,30 +1,48 @@
-use std::cmp::Reverse;
+use std::{cmp::Reverse, time::Duration};
 
 use anyhow::{anyhow, bail, Result};
-use chrono::Utc;
-use crossbeam::channel::{Receiver, Sender};
+use chrono::{DateTime, Utc};
+use crossbeam::channel::{self, Receiver, Sender};
 use priority_queue::PriorityQueue;
 use uuid::Uuid;
 
 use crate::web_server::{CancelRequestType, OrderRequest};
 
-use super::{ExpirationOrderRequest, InsertExpirationRequest, UnixTimestamp};
+use super::{ExpirationOrderRequest, InsertExpirationRequest, UnixTimestampMillis};
+
+const DEFAULT_MAX_SWEEP_INTERVAL: Duration = Duration::from_millis(1000);
 
 pub struct ExpirationHandler {
     cancellation_request_sender: Sender<OrderRequest>,
     expiration_order_request_reciever: Receiver<ExpirationOrderRequest>,
-    expiration_queue: PriorityQueue<Uuid, Reverse<UnixTimestamp>>,
+    expiration_queue: PriorityQueue<Uuid, Reverse<UnixTimestampMillis>>,
+    max_sweep_interval: Duration,
 }
 
 impl ExpirationHandler {
     pub fn new(
         cancellation_request_sender: Sender<OrderRequest>,
         expiration_order_request_reciever: Receiver<ExpirationOrderRequest>,
+    ) -> Self {
+        Self::with_max_sweep_interval(
+            cancellation_request_sender,
+            expiration_order_request_reciever,
+            DEFAULT_MAX_SWEEP_INTERVAL,
+        )
+    }
+
+    /// Handler that re-checks expirations at least every max_sweep_interval,
+    /// however far away the next expiry is
+    pub fn with_max_sweep_interval(
+        cancellation_request_sender: Sender<OrderRequest>,
+        expiration_order_request_reciever: Receiver<ExpirationOrderRequest>,
+        max_sweep_interval: Duration,
     ) -> Self {
         Self {
             cancellation_request_sender,
             expiration_order_request_reciever,
             expiration_queue: PriorityQueue::new(),
+            max_sweep_interval,
         }
     }
 
@@ -34,35 +52,75 @@ impl ExpirationHandler {
         }
     }
 
+    /// Blocks until either a request arrives or the next order is due,
+    /// waiting indefinitely while no orders are queued
+    ///
+    /// Returns once every request sender has been dropped
     pub fn run(&mut self) {
         loop {
-            if let Ok(expiration_order_request) = self.expiration_order_request_reciever.try_recv()
-            {
-                match expiration_order_request {
-                    ExpirationOrderRequest::InsertExpirationRequest(insert_expiration_request) => {
-                        let _ = self.insert_expiring_order(insert_expiration_request);
-                    }
-                    ExpirationOrderRequest::RemoveExpirationRequest(order_id) => {
-                        self.remove_expiration_request(order_id)
-                    }
+            let expiration_order_request = match self.next_sweep_timeout(Utc::now()) {
+                Some(timeout) => channel::select! {
+                    recv(self.expiration_order_request_reciever) -> request => request.map(Some),
+                    default(timeout) => Ok(None),
+                },
+                None => self.expiration_order_request_reciever.recv().map(Some),
+            };
+
+            match expiration_order_request {
+                Ok(Some(ExpirationOrderRequest::InsertExpirationRequest(
+                    insert_expiration_request,
+                ))) => {
+                    let _ = self.insert_expiring_order(insert_expiration_request);
                 }
+                Ok(Some(ExpirationOrderRequest::RemoveExpirationRequest(order_id))) => {
+                    self.remove_expiration_request(order_id)
+                }
+                Ok(Some(ExpirationOrderRequest::ForceExpireRequest(order_id))) => {
+                    let _ = self.force_expire_order(order_id);
+                }
+                Ok(None) => {}
+                // Nothing can be inserted or removed anymore, and a
+                // disconnected receiver would otherwise never block
+                Err(_) => return,
             }
 
-            if let Some(order) = self.expiration_queue.peek() {
-                if order.1 .0 < Utc::now().timestamp() {
-                    // TODO: Need to handle this error, might just be best to log it
-                    let _ = self.send_cancellation_request(*order.0);
-                    self.expiration_queue.pop();
-                }
+            self.expire_due_orders();
+        }
+    }
+
+    /// Expires an order immediately, as if its expiration date had passed
+    fn force_expire_order(&mut self, order_id: Uuid) -> Result<()> {
+        self.remove_expiration_request(order_id);
+        self.send_cancellation_request(order_id)
+    }
+
+    fn expire_due_orders(&mut self) {
+        while let Some(order) = self.expiration_queue.peek() {
+            if order.1 .0 > Utc::now().timestamp_millis() {
+                break;
             }
+            // TODO: Need to handle this error, might just be best to log it
+            let _ = self.send_cancellation_request(*order.0);
+            self.expiration_queue.pop();
         }
     }
 
+    /// How long to wait for requests before sweeping expirations
+    ///
+    /// Waits until the next order is due, but never longer than
+    /// the max sweep interval, None when no orders are queued
+    fn next_sweep_timeout(&self, now: DateTime<Utc>) -> Option<Duration> {
+        self.expiration_queue.peek().map(|(_, Reverse(timestamp))| {
+            let until_due_millis = timestamp - now.timestamp_millis();
+            Duration::from_millis(until_due_millis.max(0) as u64).min(self.max_sweep_interval)
+        })
+    }
+
     fn insert_expiring_order(
         &mut self,
         order_expiration_request: InsertExpirationRequest,
     ) -> Result<()> {
-        if order_expiration_request.timestamp < Utc::now().timestamp() {
+        if order_expiration_request.timestamp < Utc::now().timestamp_millis() {
             bail!("Timestamp in past")
         }
 
@@ -75,7 +133,7 @@ impl ExpirationHandler {
     }
 
     fn send_cancellation_request(&mut self, order_id: Uuid) -> Result<()> {
-        let order_request = OrderRequest::Cancel(CancelRequestType::Internal, order_id);
+        let order_request = OrderRequest::Cancel(CancelRequestType::Expiry, order_id);
 
         match self.cancellation_request_sender.send(order_request) {
             Ok(_) => Ok(()),
@@ -93,6 +151,11 @@ mod tests {
     use crossbeam::channel;
     use uuid::Uuid;
 
+    use crate::{
+        orderbook::{orderbook::Orderbook, MarketDataUpdate, OrderSide, OrderStatus},
+        web_server::TradeRequest,
+    };
+
     #[test]
     fn timestamps_occurring_sooner_given_higher_priority() {
         let (_, rx) = channel::unbounded();
@@ -100,7 +163,7 @@ mod tests {
         let mut handler = ExpirationHandler::new(cancel_tx, rx);
 
         let order_id_1 = Uuid::new_v4();
-        let timestamp = (Utc::now() + Duration::seconds(100)).timestamp();
+        let timestamp = (Utc::now() + Duration::seconds(100)).timestamp_millis();
         let order_expiration_request = InsertExpirationRequest {
             order_id: order_id_1,
             timestamp,
@@ -111,7 +174,7 @@ mod tests {
             .unwrap();
 
         let order_id_2 = Uuid::new_v4();
-        let timestamp = (Utc::now() + Duration::seconds(1)).timestamp();
+        let timestamp = (Utc::now() + Duration::seconds(1)).timestamp_millis();
         let order_expiration_request = InsertExpirationRequest {
             order_id: order_id_2,
             timestamp,
@@ -131,7 +194,7 @@ mod tests {
         let mut handler = ExpirationHandler::new(cancel_tx, rx);
 
         let order_id = Uuid::new_v4();
-        let timestamp = (Utc::now() + Duration::seconds(2)).timestamp();
+        let timestamp = (Utc::now() + Duration::seconds(2)).timestamp_millis();
         let order_expiration_request = InsertExpirationRequest {
             order_id,
             timestamp,
@@ -150,7 +213,7 @@ mod tests {
         let mut handler = ExpirationHandler::new(cancel_tx, rx);
 
         let order_id = Uuid::new_v4();
-        let timestamp = (Utc::now() - Duration::seconds(60)).timestamp();
+        let timestamp = (Utc::now() - Duration::seconds(60)).timestamp_millis();
         let order_expiration_request = InsertExpirationRequest {
             order_id,
             timestamp,
@@ -172,7 +235,7 @@ mod tests {
         assert!(handler.send_cancellation_request(order_uuid).is_ok());
 
         match cancel_rx.try_recv() {
-            Ok(OrderRequest::Cancel(CancelRequestType::Internal, received_uuid)) => {
+            Ok(OrderRequest::Cancel(CancelRequestType::Expiry, received_uuid)) => {
                 assert_eq!(received_uuid, order_uuid);
             }
             _ => panic!("Did not receive expected cancellation request"),
@@ -186,7 +249,7 @@ mod tests {
         let mut handler = ExpirationHandler::new(cancel_tx, rx);
 
         let order_id = Uuid::new_v4();
-        let timestamp = (Utc::now() + Duration::seconds(100)).timestamp();
+        let timestamp = (Utc::now() + Duration::seconds(100)).timestamp_millis();
         let order_expiration_request = InsertExpirationRequest {
             order_id,
             timestamp,
@@ -200,4 +263,216 @@ mod tests {
 
         assert!(handler.expiration_queue.is_empty())
     }
+
+    #[test]
+    fn force_expiring_order_sends_expiry_cancellation() {
+        let (_, rx) = channel::unbounded();
+        let (cancel_tx, cancel_rx) = channel::unbounded();
+        let mut handler = ExpirationHandler::new(cancel_tx, rx);
+
+        let order_id = Uuid::new_v4();
+        let order_expiration_request = InsertExpirationRequest {
+            order_id,
+            timestamp: (Utc::now() + Duration::seconds(100)).timestamp_millis(),
+        };
+        handler
+            .insert_expiring_order(order_expiration_request)
+            .unwrap();
+
+        assert!(handler.force_expire_order(order_id).is_ok());
+
+        assert!(handler.expiration_queue.is_empty());
+        match cancel_rx.try_recv() {
+            Ok(OrderRequest::Cancel(CancelRequestType::Expiry, received_order_id)) => {
+                assert_eq!(received_order_id, order_id);
+            }
+            _ => panic!("Did not receive expiry cancellation"),
+        }
+    }
+
+    #[test]
+    fn force_expired_order_removed_from_orderbook() {
+        let (_, rx) = channel::unbounded();
+        let (cancel_tx, cancel_rx) = channel::unbounded();
+        let mut handler = ExpirationHandler::new(cancel_tx, rx);
+        let mut orderbook = Orderbook::default();
+
+        let trade_request = TradeRequest::normal(OrderSide::Buy, 10, 1);
+        let order_id = trade_request.id;
+        orderbook
+            .place_trade_request(OrderRequest::Trade(trade_request))
+            .unwrap();
+
+        assert!(handler.force_expire_order(order_id).is_ok());
+        let cancel_request = cancel_rx.try_recv().unwrap();
+        assert!(matches!(
+            cancel_request,
+            OrderRequest::Cancel(CancelRequestType::Expiry, received_order_id)
+                if received_order_id == order_id
+        ));
+        let market_updates = orderbook.place_trade_request(cancel_request).unwrap();
+
+        assert!(matches!(
+            market_updates.as_slice(),
+            [MarketDataUpdate::Cancellation(_)]
+        ));
+        assert_eq!(
+            orderbook.order_status(order_id).status,
+            OrderStatus::Expired
+        );
+    }
+
+    #[test]
+    fn sweep_timeout_bounded_by_max_interval() {
+        let (_, rx) = channel::unbounded();
+        let (cancel_tx, _cancel_rx) = channel::unbounded();
+        let max_sweep_interval = std::time::Duration::from_millis(50);
+        let mut handler =
+            ExpirationHandler::with_max_sweep_interval(cancel_tx, rx, max_sweep_interval);
+
+        let now = Utc::now();
+        let order_expiration_request = InsertExpirationRequest {
+            order_id: Uuid::new_v4(),
+            timestamp: (now + Duration::seconds(3600)).timestamp_millis(),
+        };
+        handler
+            .insert_expiring_order(order_expiration_request)
+            .unwrap();
+
+        assert_eq!(handler.next_sweep_timeout(now), Some(max_sweep_interval));
+    }
+
+    #[test]
+    fn sweep_timeout_waits_until_next_expiry() {
+        let (_, rx) = channel::unbounded();
+        let (cancel_tx, _cancel_rx) = channel::unbounded();
+        let mut handler = ExpirationHandler::with_max_sweep_interval(
+            cancel_tx,
+            rx,
+            std::time::Duration::from_secs(60),
+        );
+
+        let now = Utc::now();
+        let order_expiration_request = InsertExpirationRequest {
+            order_id: Uuid::new_v4(),
+            timestamp: (now + Duration::seconds(2)).timestamp_millis(),
+        };
+        handler
+            .insert_expiring_order(order_expiration_request)
+            .unwrap();
+
+        let timeout = handler.next_sweep_timeout(now).unwrap();
+        assert!(timeout > std::time::Duration::from_secs(1));
+        assert!(timeout <= std::time::Duration::from_secs(3));
+    }
+
+    #[test]
+    fn sweep_timeout_none_without_queued_orders() {
+        let (_, rx) = channel::unbounded();
+        let (cancel_tx, _cancel_rx) = channel::unbounded();
+        let handler = ExpirationHandler::new(cancel_tx, rx);
+
+        assert_eq!(handler.next_sweep_timeout(Utc::now()), None);
+    }
+
+    #[test]
+    fn run_sends_cancellation_once_order_is_due() {
+        let (request_tx, request_rx) = channel::unbounded();
+        let (cancel_tx, cancel_rx) = channel::unbounded();
+        let mut handler = ExpirationHandler::new(cancel_tx, request_rx);
+        let handle = std::thread::spawn(move || handler.run());
+
+        let order_id = Uuid::new_v4();
+        let insert_expiration_request = InsertExpirationRequest {
+            order_id,
+            timestamp: (Utc::now() + Duration::milliseconds(200)).timestamp_millis(),
+        };
+        assert!(request_tx
+            .send(ExpirationOrderRequest::InsertExpirationRequest(
+                insert_expiration_request
+            ))
+            .is_ok());
+
+        match cancel_rx.recv_timeout(std::time::Duration::from_secs(2)) {
+            Ok(OrderRequest::Cancel(CancelRequestType::Expiry, received_order_id)) => {
+                assert_eq!(received_order_id, order_id)
+            }
+            _ => panic!("Did not receive expiry cancellation"),
+        }
+
+        drop(request_tx);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn run_wakes_to_expire_order_without_further_requests() {
+        let (request_tx, request_rx) = channel::unbounded();
+        let (cancel_tx, cancel_rx) = channel::unbounded();
+        let max_sweep_interval = std::time::Duration::from_millis(50);
+        let mut handler =
+            ExpirationHandler::with_max_sweep_interval(cancel_tx, request_rx, max_sweep_interval);
+        let handle = std::thread::spawn(move || handler.run());
+
+        let order_id = Uuid::new_v4();
+        let expires_at = Utc::now() + Duration::milliseconds(150);
+        let insert_expiration_request = InsertExpirationRequest {
+            order_id,
+            timestamp: expires_at.timestamp_millis(),
+        };
+        assert!(request_tx
+            .send(ExpirationOrderRequest::InsertExpirationRequest(
+                insert_expiration_request
+            ))
+            .is_ok());
+
+        // Nothing else is sent, so only the sweep timeout can wake the handler
+        let deadline = std::time::Duration::from_millis(150) + max_sweep_interval * 4;
+        match cancel_rx.recv_timeout(deadline) {
+            Ok(OrderRequest::Cancel(CancelRequestType::Expiry, received_order_id)) => {
+                assert_eq!(received_order_id, order_id);
+                assert!(Utc::now() >= expires_at);
+            }
+            _ => panic!("Did not receive expiry cancellation within the sweep interval"),
+        }
+
+        drop(request_tx);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn run_expires_orders_milliseconds_apart_in_order() {
+        let (request_tx, request_rx) = channel::unbounded();
+        let (cancel_tx, cancel_rx) = channel::unbounded();
+        let mut handler = ExpirationHandler::new(cancel_tx, request_rx);
+        let handle = std::thread::spawn(move || handler.run());
+
+        let now = Utc::now();
+        let first_order_id = Uuid::new_v4();
+        let second_order_id = Uuid::new_v4();
+        for (order_id, offset_millis) in [(second_order_id, 110), (first_order_id, 100)] {
+            let insert_expiration_request = InsertExpirationRequest {
+                order_id,
+                timestamp: (now + Duration::milliseconds(offset_millis)).timestamp_millis(),
+            };
+            assert!(request_tx
+                .send(ExpirationOrderRequest::InsertExpirationRequest(
+                    insert_expiration_request
+                ))
+                .is_ok());
+        }
+
+        let mut expired_order_ids = vec![];
+        for _ in 0..2 {
+            match cancel_rx.recv_timeout(std::time::Duration::from_secs(2)) {
+                Ok(OrderRequest::Cancel(CancelRequestType::Expiry, order_id)) => {
+                    expired_order_ids.push(order_id)
+                }
+                _ => panic!("Did not receive expiry cancellation"),
+            }
+        }
+        assert_eq!(expired_order_ids, vec![first_order_id, second_order_id]);
+
+        drop(request_tx);
+        handle.join().unwrap();
+    }
 }
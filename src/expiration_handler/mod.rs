@@ -2,14 +2,17 @@ use uuid::Uuid;
 
 pub mod expiration_handler;
 
-type UnixTimestamp = i64;
+type UnixTimestampMillis = i64;
 
 pub enum ExpirationOrderRequest {
     InsertExpirationRequest(InsertExpirationRequest),
     RemoveExpirationRequest(Uuid),
+    /// Expires the order now, whatever its expiration date
+    ForceExpireRequest(Uuid),
 }
 
 pub struct InsertExpirationRequest {
-    pub timestamp: UnixTimestamp,
+    /// Unix timestamp in milliseconds at which the order expires
+    pub timestamp: UnixTimestampMillis,
     pub order_id: Uuid,
 }
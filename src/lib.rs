@@ -1,5 +1,9 @@
 pub mod expiration_handler;
 pub mod market_data_outbox;
 pub mod metrics;
+pub mod order_engine;
 pub mod orderbook;
+#[cfg(feature = "parquet")]
+pub mod parquet_export;
+pub mod text_protocol;
 pub mod web_server;
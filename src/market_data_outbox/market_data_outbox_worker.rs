@@ -1,27 +1,92 @@
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::{
+    fmt::Debug,
+    mem,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    panic::{self, AssertUnwindSafe},
+    time::{Duration, Instant},
+};
 
 use anyhow::Result;
 
-use crate::orderbook::MarketDataUpdate;
-use borsh::BorshSerialize;
-use crossbeam::channel::Receiver;
+use crate::{
+    metrics::{OVERSIZED_UPDATES_DROPPED, SERIALIZATION_FAILURES},
+    orderbook::MarketDataUpdate,
+};
+use borsh::{BorshDeserialize, BorshSerialize};
+use chrono::Utc;
+use crossbeam::channel::{self, Receiver};
 use socket2::{Domain, Protocol, Socket, Type};
 use tokio::net::UdpSocket;
 
 pub const MULTICAST_PORT: u16 = 8888;
 pub const MULTICAST_ADDR: Ipv4Addr = Ipv4Addr::new(239, 255, 10, 10);
+pub const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+/// Largest encoded update sent, fits an Ethernet frame without fragmenting,
+/// batches are split to fit and listeners size their buffers to it
+pub const MAX_DATAGRAM_BYTES: usize = 1472;
+/// Encoded bytes of a sequenced batch besides its updates, the
+/// sequence, variant tag and update count
+const SEQUENCED_BATCH_OVERHEAD: usize = 8 + 1 + 4;
+
+/// Update as sent on the feed, numbered so consumers can detect dropped packets
+///
+/// Heartbeats repeat the sequence of the last update rather than taking a new one
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq)]
+pub struct SequencedMarketDataUpdate {
+    pub sequence: u64,
+    pub update: MarketDataUpdate,
+}
+
+/// Numbers outgoing updates consecutively, starting from 1
+#[derive(Debug, Default)]
+struct MarketDataSequencer {
+    last_sequence: u64,
+}
+
+impl MarketDataSequencer {
+    fn sequence(&mut self, update: MarketDataUpdate) -> SequencedMarketDataUpdate {
+        self.last_sequence += 1;
+        SequencedMarketDataUpdate {
+            sequence: self.last_sequence,
+            update,
+        }
+    }
+
+    fn heartbeat(&self, timestamp: i64) -> SequencedMarketDataUpdate {
+        SequencedMarketDataUpdate {
+            sequence: self.last_sequence,
+            update: MarketDataUpdate::Heartbeat {
+                timestamp,
+                last_sequence: self.last_sequence,
+            },
+        }
+    }
+}
 
 pub struct MarketDataWorker {
     trade_reciever: Receiver<MarketDataUpdate>,
     socket: UdpSocket,
+    sequencer: MarketDataSequencer,
+    heartbeat_ticker: Receiver<Instant>,
 }
 
 impl MarketDataWorker {
     pub fn new(trade_reciever: Receiver<MarketDataUpdate>) -> Self {
+        Self::new_with_interval(trade_reciever, DEFAULT_HEARTBEAT_INTERVAL)
+    }
+
+    /// Worker that sends a heartbeat every heartbeat_interval, on a
+    /// fixed schedule whether or not updates are flowing
+    pub fn new_with_interval(
+        trade_reciever: Receiver<MarketDataUpdate>,
+        heartbeat_interval: Duration,
+    ) -> Self {
         let socket = MarketDataWorker::setup_socket().expect("Should be able to create socket");
         Self {
             trade_reciever,
             socket,
+            sequencer: MarketDataSequencer::default(),
+            heartbeat_ticker: channel::tick(heartbeat_interval),
         }
     }
 
@@ -37,14 +102,234 @@ impl MarketDataWorker {
     pub async fn do_work(&mut self) {
         let dest_addr = SocketAddr::new(IpAddr::V4(MULTICAST_ADDR), MULTICAST_PORT);
         println!("Waiting to recieve market data");
-        loop {
-            if let Ok(trade) = self.trade_reciever.recv() {
-                println!("recieved trade: {:?}", trade);
-                let mut buffer: Vec<u8> = Vec::new();
-                if trade.serialize(&mut buffer).is_ok() {
-                    let _ = self.socket.send_to(&buffer, &dest_addr).await;
+        // Stops once every sender is gone, as nothing more can be published
+        while let Some(sequenced_updates) = next_outgoing_updates(
+            &self.trade_reciever,
+            &mut self.sequencer,
+            &self.heartbeat_ticker,
+        ) {
+            for sequenced_update in sequenced_updates {
+                let Some(buffer) = encode_update(&sequenced_update) else {
+                    continue;
+                };
+                // Dropped after sequencing, so listeners see the gap
+                if buffer.len() > MAX_DATAGRAM_BYTES {
+                    OVERSIZED_UPDATES_DROPPED.inc();
+                    continue;
                 }
+                let _ = self.socket.send_to(&buffer, &dest_addr).await;
+            }
+        }
+    }
+}
+
+/// Waits for the next update to send, split into datagram sized batches,
+/// or a heartbeat when heartbeat_ticker ticks, None once the channel
+/// disconnects
+fn next_outgoing_updates(
+    trade_reciever: &Receiver<MarketDataUpdate>,
+    sequencer: &mut MarketDataSequencer,
+    heartbeat_ticker: &Receiver<Instant>,
+) -> Option<Vec<SequencedMarketDataUpdate>> {
+    channel::select! {
+        recv(trade_reciever) -> trade => {
+            let trade = trade.ok()?;
+            println!("recieved trade: {:?}", trade);
+            // Sequenced before encoding, so a dropped update shows as a gap
+            Some(
+                split_batch(trade, MAX_DATAGRAM_BYTES)
+                    .into_iter()
+                    .map(|update| sequencer.sequence(update))
+                    .collect(),
+            )
+        },
+        recv(heartbeat_ticker) -> _ => {
+            Some(vec![sequencer.heartbeat(Utc::now().timestamp_millis())])
+        },
+    }
+}
+
+/// Splits a batch into consecutive batches that each encode within
+/// max_bytes once sequenced, other updates are left whole
+///
+/// An update too large on its own still gets a batch of its own
+fn split_batch(update: MarketDataUpdate, max_bytes: usize) -> Vec<MarketDataUpdate> {
+    let MarketDataUpdate::Batch(updates) = update else {
+        return vec![update];
+    };
+
+    let mut batches = vec![];
+    let mut batch = vec![];
+    let mut batch_bytes = SEQUENCED_BATCH_OVERHEAD;
+    for update in updates {
+        let update_bytes = borsh::object_length(&update).unwrap_or(usize::MAX);
+        if !batch.is_empty() && batch_bytes.saturating_add(update_bytes) > max_bytes {
+            batches.push(MarketDataUpdate::Batch(mem::take(&mut batch)));
+            batch_bytes = SEQUENCED_BATCH_OVERHEAD;
+        }
+        batch_bytes = batch_bytes.saturating_add(update_bytes);
+        batch.push(update);
+    }
+    if !batch.is_empty() || batches.is_empty() {
+        batches.push(MarketDataUpdate::Batch(batch));
+    }
+    batches
+}
+
+/// Serializes an update for sending, None if it failed or panicked
+///
+/// Failures are counted and logged rather than propagated, so
+/// one bad update is dropped without taking down the feed
+fn encode_update<T: BorshSerialize + Debug>(update: &T) -> Option<Vec<u8>> {
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut buffer: Vec<u8> = Vec::new();
+        update.serialize(&mut buffer).map(|()| buffer)
+    }));
+
+    match result {
+        Ok(Ok(buffer)) => Some(buffer),
+        Ok(Err(_)) | Err(_) => {
+            SERIALIZATION_FAILURES.inc();
+            eprintln!(
+                "Failed to serialize market data update, skipping: {:?}",
+                update
+            );
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Write};
+
+    use uuid::Uuid;
+
+    use crate::metrics::{reset_metrics, METRICS_TEST_LOCK};
+
+    use super::*;
+
+    #[derive(Debug)]
+    enum UnserializableUpdate {
+        Error,
+        Panic,
+    }
+
+    impl BorshSerialize for UnserializableUpdate {
+        fn serialize<W: Write>(&self, _writer: &mut W) -> io::Result<()> {
+            match self {
+                UnserializableUpdate::Error => Err(io::Error::other("mock serialization error")),
+                UnserializableUpdate::Panic => panic!("mock serialization panic"),
+            }
+        }
+    }
+
+    #[test]
+    fn failed_serialization_counted_and_feed_continues() {
+        let _guard = METRICS_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        reset_metrics();
+
+        assert!(encode_update(&UnserializableUpdate::Error).is_none());
+        assert!(encode_update(&UnserializableUpdate::Panic).is_none());
+        assert_eq!(SERIALIZATION_FAILURES.get(), 2);
+
+        let update = MarketDataUpdate::OrderRejected {
+            id: Uuid::new_v4(),
+            reason: "rejected".to_string(),
+        };
+        assert!(encode_update(&update).is_some());
+        assert_eq!(SERIALIZATION_FAILURES.get(), 2);
+    }
+
+    #[test]
+    fn sequenced_updates_round_trip_with_increasing_sequence() {
+        let mut sequencer = MarketDataSequencer::default();
+        let updates = [
+            MarketDataUpdate::OrderRejected {
+                id: Uuid::new_v4(),
+                reason: "rejected".to_string(),
+            },
+            MarketDataUpdate::Batch(vec![]),
+        ];
+
+        let sequenced_updates: Vec<SequencedMarketDataUpdate> = updates
+            .into_iter()
+            .map(|update| sequencer.sequence(update))
+            .collect();
+
+        assert_eq!(sequenced_updates[0].sequence, 1);
+        assert_eq!(sequenced_updates[1].sequence, 2);
+        for sequenced_update in sequenced_updates {
+            let buffer = encode_update(&sequenced_update).unwrap();
+            assert_eq!(
+                SequencedMarketDataUpdate::try_from_slice(&buffer).unwrap(),
+                sequenced_update
+            );
+        }
+    }
+
+    #[test]
+    fn heartbeat_sent_when_no_updates_flow() {
+        let (trade_sender, trade_reciever) = crossbeam::channel::unbounded();
+        let mut sequencer = MarketDataSequencer::default();
+        let heartbeat_ticker = channel::tick(Duration::from_millis(10));
+
+        let update = MarketDataUpdate::Batch(vec![]);
+        trade_sender.send(update.clone()).unwrap();
+        let sent_updates =
+            next_outgoing_updates(&trade_reciever, &mut sequencer, &heartbeat_ticker).unwrap();
+        let idle_updates =
+            next_outgoing_updates(&trade_reciever, &mut sequencer, &heartbeat_ticker).unwrap();
+
+        assert_eq!(
+            sent_updates,
+            vec![SequencedMarketDataUpdate {
+                sequence: 1,
+                update
+            }]
+        );
+        let [idle_update] = idle_updates.try_into().unwrap();
+        assert_eq!(idle_update.sequence, 1);
+        assert!(matches!(
+            idle_update.update,
+            MarketDataUpdate::Heartbeat {
+                last_sequence: 1,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn large_batch_split_into_datagram_sized_batches() {
+        let (trade_sender, trade_reciever) = crossbeam::channel::unbounded();
+        let mut sequencer = MarketDataSequencer::default();
+        let updates: Vec<MarketDataUpdate> = (0..100)
+            .map(|_| MarketDataUpdate::OrderRejected {
+                id: Uuid::new_v4(),
+                reason: "rejected".to_string(),
+            })
+            .collect();
+
+        trade_sender
+            .send(MarketDataUpdate::Batch(updates.clone()))
+            .unwrap();
+        let sent_updates =
+            next_outgoing_updates(&trade_reciever, &mut sequencer, &channel::never()).unwrap();
+
+        assert!(sent_updates.len() > 1);
+        let mut received_updates = vec![];
+        for (index, sent_update) in sent_updates.into_iter().enumerate() {
+            assert_eq!(sent_update.sequence, index as u64 + 1);
+            let buffer = encode_update(&sent_update).unwrap();
+            assert!(buffer.len() <= MAX_DATAGRAM_BYTES);
+            match SequencedMarketDataUpdate::try_from_slice(&buffer)
+                .unwrap()
+                .update
+            {
+                MarketDataUpdate::Batch(batch) => received_updates.extend(batch),
+                _ => panic!("Expected batch market data update"),
             }
         }
+        assert_eq!(received_updates, updates);
     }
 }
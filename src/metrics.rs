@@ -1,5 +1,31 @@
 use lazy_static::lazy_static;
-use prometheus::{register_histogram, register_int_counter, Histogram, IntCounter, Registry};
+use prometheus::{
+    register_histogram, register_int_counter, register_int_gauge, Histogram, IntCounter, IntGauge,
+    Registry,
+};
+
+/// Matching takes microseconds, far below the default buckets tuned for
+/// seconds scale request latencies
+const MATCHING_DURATION_BUCKETS: &[f64] = &[
+    0.000_001,
+    0.000_002_5,
+    0.000_005,
+    0.000_01,
+    0.000_025,
+    0.000_05,
+    0.000_1,
+    0.000_25,
+    0.000_5,
+    0.001,
+    0.002_5,
+    0.005,
+    0.01,
+];
+
+/// Effective spreads are in price ticks, from half a tick upwards
+const EFFECTIVE_SPREAD_BUCKETS: &[f64] = &[
+    0.5, 1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0, 200.0, 500.0, 1000.0,
+];
 
 lazy_static! {
     pub static ref REGISTRY: Registry = Registry::new();
@@ -15,9 +41,46 @@ lazy_static! {
         register_int_counter!("order_counter", "Number orders recieved").unwrap();
     pub static ref TRADE_COUNTER: IntCounter =
         register_int_counter!("trade_counter", "Number trades processed").unwrap();
+    pub static ref EMPTY_MATCH_PASSES: IntCounter = register_int_counter!(
+        "empty_match_passes",
+        "Number matching passes with crossing prices that committed no trades"
+    )
+    .unwrap();
+    pub static ref EMPTY_PRICE_LEVELS_SKIPPED: IntCounter = register_int_counter!(
+        "empty_price_levels_skipped",
+        "Number price levels with no orders skipped while matching"
+    )
+    .unwrap();
+    pub static ref WORKER_PANICS: IntCounter = register_int_counter!(
+        "worker_panics",
+        "Number order requests skipped after panicking in the worker"
+    )
+    .unwrap();
+    pub static ref SERIALIZATION_FAILURES: IntCounter = register_int_counter!(
+        "serialization_failures",
+        "Number market data updates dropped after failing to serialize"
+    )
+    .unwrap();
+    pub static ref OVERSIZED_UPDATES_DROPPED: IntCounter = register_int_counter!(
+        "oversized_updates_dropped",
+        "Number market data updates dropped for not fitting in a datagram"
+    )
+    .unwrap();
+    pub static ref ORDER_BACKLOG: IntGauge = register_int_gauge!(
+        "order_backlog",
+        "Number order requests queued for the order engine"
+    )
+    .unwrap();
+    pub static ref EFFECTIVE_SPREAD: Histogram = register_histogram!(
+        "effective_spread",
+        "Twice the distance between each trade's price and the mid when its aggressor arrived",
+        EFFECTIVE_SPREAD_BUCKETS.to_vec()
+    )
+    .unwrap();
     pub static ref MATCHING_DURATION: Histogram = register_histogram!(
         "matching_duration",
-        "Duration to match order with resting order"
+        "Duration in seconds to match order with resting order",
+        MATCHING_DURATION_BUCKETS.to_vec()
     )
     .unwrap();
 }
@@ -47,4 +110,77 @@ pub fn register_custom_metrics() {
     REGISTRY
         .register(Box::new(MATCHING_DURATION.clone()))
         .expect("collector can be registered");
+
+    REGISTRY
+        .register(Box::new(EMPTY_MATCH_PASSES.clone()))
+        .expect("collector can be registered");
+
+    REGISTRY
+        .register(Box::new(EMPTY_PRICE_LEVELS_SKIPPED.clone()))
+        .expect("collector can be registered");
+
+    REGISTRY
+        .register(Box::new(WORKER_PANICS.clone()))
+        .expect("collector can be registered");
+
+    REGISTRY
+        .register(Box::new(SERIALIZATION_FAILURES.clone()))
+        .expect("collector can be registered");
+
+    REGISTRY
+        .register(Box::new(OVERSIZED_UPDATES_DROPPED.clone()))
+        .expect("collector can be registered");
+
+    REGISTRY
+        .register(Box::new(ORDER_BACKLOG.clone()))
+        .expect("collector can be registered");
+
+    REGISTRY
+        .register(Box::new(EFFECTIVE_SPREAD.clone()))
+        .expect("collector can be registered");
+}
+
+/// Held by tests asserting exact metric values, the metrics are
+/// process wide and tests run in parallel
+#[cfg(test)]
+pub static METRICS_TEST_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+/// Zeroes the counters and gauges, callers hold METRICS_TEST_LOCK
+///
+/// Histograms can't be reset, compare their sample counts instead
+#[cfg(test)]
+pub fn reset_metrics() {
+    for counter in [
+        &*REQUESTS_COUNTER,
+        &*ORDERS_FILLED_COUNTER,
+        &*ORDER_COUNTER,
+        &*TRADE_COUNTER,
+        &*EMPTY_MATCH_PASSES,
+        &*EMPTY_PRICE_LEVELS_SKIPPED,
+        &*WORKER_PANICS,
+        &*SERIALIZATION_FAILURES,
+        &*OVERSIZED_UPDATES_DROPPED,
+    ] {
+        counter.reset();
+    }
+    ORDER_BACKLOG.set(0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metric_reads_expected_value_after_reset() {
+        let _guard = METRICS_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        WORKER_PANICS.inc();
+        ORDER_BACKLOG.set(5);
+
+        reset_metrics();
+        assert_eq!(WORKER_PANICS.get(), 0);
+        assert_eq!(ORDER_BACKLOG.get(), 0);
+
+        WORKER_PANICS.inc();
+        assert_eq!(WORKER_PANICS.get(), 1);
+    }
 }
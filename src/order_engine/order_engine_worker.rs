@@ -0,0 +1,218 @@
+use std::panic::{self, AssertUnwindSafe};
+
+use crossbeam::channel::{self, Receiver};
+
+use crate::{metrics::WORKER_PANICS, orderbook::orderbook::Orderbook, web_server::OrderRequest};
+
+pub struct OrderEngineWorker {
+    order_request_reciever: Receiver<OrderRequest>,
+    cancel_request_reciever: Option<Receiver<OrderRequest>>,
+    orderbook: Orderbook,
+}
+
+impl OrderEngineWorker {
+    pub fn new(order_request_reciever: Receiver<OrderRequest>, orderbook: Orderbook) -> Self {
+        Self {
+            order_request_reciever,
+            cancel_request_reciever: None,
+            orderbook,
+        }
+    }
+
+    /// Worker that always drains the cancel channel before taking
+    /// the next request from the order channel
+    ///
+    /// A cancel can overtake the order it refers to if both are
+    /// queued, in which case the cancel finds nothing to cancel
+    pub fn with_priority_cancels(
+        order_request_reciever: Receiver<OrderRequest>,
+        cancel_request_reciever: Receiver<OrderRequest>,
+        orderbook: Orderbook,
+    ) -> Self {
+        Self {
+            order_request_reciever,
+            cancel_request_reciever: Some(cancel_request_reciever),
+            orderbook,
+        }
+    }
+
+    pub fn run(&mut self) {
+        loop {
+            if let Some(order_request) = self.next_order_request() {
+                self.process_order_request(order_request);
+            }
+        }
+    }
+
+    fn next_order_request(&self) -> Option<OrderRequest> {
+        let Some(cancel_request_reciever) = &self.cancel_request_reciever else {
+            return self.order_request_reciever.recv().ok();
+        };
+
+        if let Ok(cancel_request) = cancel_request_reciever.try_recv() {
+            return Some(cancel_request);
+        }
+
+        channel::select! {
+            recv(cancel_request_reciever) -> cancel_request => cancel_request.ok(),
+            recv(self.order_request_reciever) -> order_request => order_request.ok(),
+        }
+    }
+
+    /// Places order request, surviving a panic while doing so
+    ///
+    /// A request that panics is counted and skipped, with
+    /// anything it left uncommitted discarded, so a single
+    /// bad request can't stop the engine
+    fn process_order_request(&mut self, order_request: OrderRequest) {
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            self.orderbook.place_trade_request(order_request)
+        }));
+
+        if result.is_err() {
+            WORKER_PANICS.inc();
+            self.orderbook.discard_uncommitted();
+        }
+    }
+}
+
+/// Pins the calling thread to a CPU core, so the worker isn't migrated
+///
+/// Returns whether the thread was pinned
+#[cfg(feature = "core_affinity")]
+pub fn pin_current_thread(core_id: usize) -> bool {
+    core_affinity::set_for_current(core_affinity::CoreId { id: core_id })
+}
+
+#[cfg(test)]
+mod tests {
+    use crossbeam::channel;
+    use tokio::sync::oneshot;
+    use uuid::Uuid;
+
+    use crate::{
+        metrics::{reset_metrics, METRICS_TEST_LOCK},
+        orderbook::{MarketDataUpdate, Order, OrderSide, OrderType},
+        web_server::{CancelRequestType, TradeRequest},
+    };
+
+    use super::*;
+
+    #[test]
+    fn panicking_request_does_not_kill_worker() {
+        let (_, rx) = channel::unbounded();
+        let mut orderbook = Orderbook::default();
+        let untracked_order = Order::new(OrderType::Normal, OrderSide::Sell, 1, 1, 0);
+        orderbook.insert_untracked_level_order(untracked_order);
+        let mut worker = OrderEngineWorker::new(rx, orderbook);
+
+        let _guard = METRICS_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        reset_metrics();
+        let crossing_request = TradeRequest::normal(OrderSide::Buy, 1, 1);
+        worker.process_order_request(OrderRequest::Trade(crossing_request));
+        assert_eq!(WORKER_PANICS.get(), 1);
+
+        let (reply_sender, mut reply_receiver) = oneshot::channel();
+        worker.process_order_request(OrderRequest::RecentTrades(10, reply_sender));
+        assert!(reply_receiver.try_recv().unwrap().is_empty());
+    }
+
+    #[test]
+    fn panicking_request_discards_uncommitted_fills() {
+        let (_, rx) = channel::unbounded();
+        let mut orderbook = Orderbook::default();
+        let sell_request = TradeRequest::normal(OrderSide::Sell, 1, 1);
+        orderbook
+            .place_trade_request(OrderRequest::Trade(sell_request))
+            .unwrap();
+        let sell_order_id = orderbook.snapshot().first().unwrap().id;
+        let untracked_order = Order::new(OrderType::Normal, OrderSide::Sell, 1, 1, 0);
+        orderbook.insert_untracked_level_order(untracked_order);
+        let mut worker = OrderEngineWorker::new(rx, orderbook);
+
+        let _guard = METRICS_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        reset_metrics();
+        let crossing_request = TradeRequest::normal(OrderSide::Buy, 1, 2);
+        worker.process_order_request(OrderRequest::Trade(crossing_request));
+        assert_eq!(WORKER_PANICS.get(), 1);
+
+        for order in worker.orderbook.snapshot() {
+            assert_eq!(order.virtual_remaining_quantity, order.remaining_quantity);
+        }
+
+        let buy_request = TradeRequest::normal(OrderSide::Buy, 1, 1);
+        worker.process_order_request(OrderRequest::Trade(buy_request));
+        assert_eq!(WORKER_PANICS.get(), 1);
+        assert_eq!(
+            worker
+                .orderbook
+                .recent_trades(1)
+                .first()
+                .unwrap()
+                .ask_order_id(),
+            sell_order_id
+        );
+    }
+
+    #[test]
+    fn priority_cancel_processed_before_queued_orders() {
+        let (order_tx, order_rx) = channel::unbounded();
+        let (cancel_tx, cancel_rx) = channel::unbounded();
+        let worker =
+            OrderEngineWorker::with_priority_cancels(order_rx, cancel_rx, Orderbook::default());
+
+        for price in 1..=3 {
+            let trade_request = TradeRequest::normal(OrderSide::Buy, price, 1);
+            order_tx.send(OrderRequest::Trade(trade_request)).unwrap();
+        }
+        let cancelled_order_id = Uuid::new_v4();
+        cancel_tx
+            .send(OrderRequest::Cancel(
+                CancelRequestType::User,
+                cancelled_order_id,
+            ))
+            .unwrap();
+
+        assert!(matches!(
+            worker.next_order_request(),
+            Some(OrderRequest::Cancel(_, order_id)) if order_id == cancelled_order_id
+        ));
+        assert!(matches!(
+            worker.next_order_request(),
+            Some(OrderRequest::Trade(_))
+        ));
+    }
+
+    #[cfg(feature = "core_affinity")]
+    #[test]
+    fn pins_thread_to_configured_core() {
+        let core_id = core_affinity::get_core_ids().unwrap()[0].id;
+
+        let pinned = std::thread::spawn(move || pin_current_thread(core_id))
+            .join()
+            .unwrap();
+
+        assert!(pinned);
+    }
+
+    #[test]
+    fn crossing_orders_publish_trade() {
+        let (_, rx) = channel::unbounded();
+        let (market_data_sender, market_data_receiver) = channel::unbounded();
+        let mut worker = OrderEngineWorker::new(rx, Orderbook::new(Some(market_data_sender)));
+
+        let sell_request = TradeRequest::normal(OrderSide::Sell, 1, 1);
+        let buy_request = TradeRequest::normal(OrderSide::Buy, 1, 1);
+        worker.process_order_request(OrderRequest::Trade(sell_request));
+        worker.process_order_request(OrderRequest::Trade(buy_request));
+
+        let updates: Vec<MarketDataUpdate> = market_data_receiver.try_iter().collect();
+        assert_eq!(updates.len(), 1);
+        match updates.first().unwrap() {
+            MarketDataUpdate::Trade(trade) => {
+                assert_eq!(worker.orderbook.recent_trades(1).first().unwrap(), trade)
+            }
+            _ => panic!("Expected trade market data update"),
+        }
+    }
+}
@@ -1,4 +1,7 @@
+use std::fmt;
+
 use borsh::{BorshDeserialize, BorshSerialize};
+use chrono::Utc;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
@@ -6,11 +9,12 @@ use crate::web_server::CancelRequestType;
 
 pub mod orderbook;
 pub mod orderlevels;
+pub mod state_machine;
 
 type Price = i64;
 type Quantity = u64;
 
-#[derive(Copy, Clone, PartialEq, Debug, BorshSerialize, BorshDeserialize)]
+#[derive(Copy, Clone, PartialEq, Debug, BorshSerialize, BorshDeserialize, Serialize)]
 pub struct Order {
     pub type_: OrderType,
     pub id: Uuid,
@@ -20,6 +24,17 @@ pub struct Order {
     pub remaining_quantity: Quantity,
     pub minimum_quantity: Quantity,
     pub virtual_remaining_quantity: Quantity,
+    /// Millisecond timestamp of when the order was received
+    pub inserted_at: i64,
+    /// Priority within a price level, assigned when the order rests,
+    /// lower sequences match first
+    pub sequence: u64,
+    /// Fraction of the quantity a kill order must fill on entry for
+    /// any of its trades to commit, a full fill when None
+    pub min_fill_ratio: Option<f64>,
+    /// Participant the order belongs to, orders without one are never
+    /// checked for self trades
+    pub account_id: Option<Uuid>,
 }
 
 impl Order {
@@ -39,6 +54,20 @@ impl Order {
             remaining_quantity: quantity,
             minimum_quantity,
             virtual_remaining_quantity: quantity,
+            inserted_at: Utc::now().timestamp_millis(),
+            sequence: 0,
+            min_fill_ratio: None,
+            account_id: None,
+        }
+    }
+
+    /// Quantity a kill order must fill on entry for its trades to commit
+    pub fn kill_fill_threshold(&self) -> Quantity {
+        match self.min_fill_ratio {
+            Some(min_fill_ratio) => {
+                (min_fill_ratio * self.initial_quantity as f64).ceil() as Quantity
+            }
+            None => self.initial_quantity,
         }
     }
 }
@@ -48,7 +77,14 @@ impl Order {
 )]
 pub enum OrderType {
     Normal,
+    /// Fill or kill, trades only if the whole order, or its
+    /// min fill ratio, fills on entry
     Kill,
+    /// Fills what it can on entry, any remainder is cancelled
+    ImmediateOrCancel,
+    /// Matches at any price, trading at the resting orders' prices,
+    /// any remainder is cancelled
+    Market,
 }
 
 #[derive(
@@ -59,7 +95,7 @@ pub enum OrderSide {
     Sell,
 }
 
-#[derive(BorshDeserialize, Debug, PartialEq, BorshSerialize, Clone)]
+#[derive(BorshDeserialize, Debug, PartialEq, BorshSerialize, Clone, Serialize)]
 struct TradeInfo {
     order_id: Uuid,
     price: Price,
@@ -67,10 +103,50 @@ struct TradeInfo {
 }
 
 /// matched order, aggregate of bid and ask
-#[derive(Debug, PartialEq, BorshSerialize, BorshDeserialize, Clone)]
+#[derive(Debug, PartialEq, BorshSerialize, BorshDeserialize, Clone, Serialize)]
 pub struct Trade {
     bid: TradeInfo,
     ask: TradeInfo,
+    aggressor_side: OrderSide,
+    resting_order_audit: TradeAudit,
+    /// Twice the distance between the execution price and the mid when
+    /// the aggressor arrived, None if the book was one sided
+    effective_spread: Option<f64>,
+}
+
+impl Trade {
+    pub fn bid_order_id(&self) -> Uuid {
+        self.bid.order_id
+    }
+
+    pub fn ask_order_id(&self) -> Uuid {
+        self.ask.order_id
+    }
+
+    pub fn aggressor_side(&self) -> OrderSide {
+        self.aggressor_side
+    }
+
+    /// Price the trade executed at, which is the resting order's price
+    pub fn price(&self) -> Price {
+        match self.aggressor_side {
+            OrderSide::Buy => self.ask.price,
+            OrderSide::Sell => self.bid.price,
+        }
+    }
+
+    pub fn quantity(&self) -> Quantity {
+        self.bid.quantity
+    }
+}
+
+/// Price-time priority evidence for the resting side of a trade
+#[derive(Copy, Debug, PartialEq, BorshSerialize, BorshDeserialize, Clone, Serialize)]
+pub struct TradeAudit {
+    /// Position of the resting order within its price level at match time
+    queue_position: usize,
+    /// Milliseconds the resting order had rested before the match
+    resting_duration_ms: i64,
 }
 
 // TODO: Simplify
@@ -79,6 +155,95 @@ pub enum ProcessTradeError {
     MinQuantityNotMet(Vec<MinQuantityNotMetTypes>),
     PriceDiscrepancy,
     FillQuantityHigherThanRemaining,
+    /// A quantity subtraction would have gone below zero
+    QuantityUnderflow,
+}
+
+impl fmt::Display for ProcessTradeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProcessTradeError::MinQuantityNotMet(_) => write!(f, "Minimum quantity not met"),
+            ProcessTradeError::PriceDiscrepancy => write!(f, "Trade prices don't match"),
+            ProcessTradeError::FillQuantityHigherThanRemaining => {
+                write!(f, "Fill quantity higher than remaining")
+            }
+            ProcessTradeError::QuantityUnderflow => write!(f, "Quantity underflow"),
+        }
+    }
+}
+
+impl std::error::Error for ProcessTradeError {}
+
+/// Subtracts quantities, erroring rather than wrapping if
+/// quantity is less than subtrahend
+pub(crate) fn checked_quantity_sub(
+    quantity: Quantity,
+    subtrahend: Quantity,
+) -> Result<Quantity, ProcessTradeError> {
+    quantity
+        .checked_sub(subtrahend)
+        .ok_or(ProcessTradeError::QuantityUnderflow)
+}
+
+/// Reason an order request was rejected before matching
+#[derive(Debug, PartialEq)]
+pub enum MatchError {
+    /// The book is still warming up and only accepts cancels
+    Warmup,
+    /// The order would neither trade nor rest, so it would have no effect
+    NothingToDo,
+    /// The order failed the orderbook's credit check
+    Credit(CreditError),
+    /// The order's price is outside the orderbook's price range or off its tick
+    PriceOutOfRange,
+}
+
+impl fmt::Display for MatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MatchError::Warmup => write!(f, "Orderbook is warming up"),
+            MatchError::NothingToDo => write!(f, "Order would neither trade nor rest"),
+            MatchError::Credit(credit_error) => write!(f, "Credit check failed: {}", credit_error),
+            MatchError::PriceOutOfRange => write!(f, "Price outside the orderbook's price range"),
+        }
+    }
+}
+
+impl std::error::Error for MatchError {}
+
+/// Reason an order failed a credit check
+#[derive(Debug, PartialEq)]
+pub enum CreditError {
+    /// The account can't fund the order
+    InsufficientFunds,
+    /// The order would take the account over its credit limit
+    LimitExceeded,
+}
+
+impl fmt::Display for CreditError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CreditError::InsufficientFunds => write!(f, "Insufficient funds"),
+            CreditError::LimitExceeded => write!(f, "Credit limit exceeded"),
+        }
+    }
+}
+
+impl std::error::Error for CreditError {}
+
+/// Checks an order against its account's available funds before it matches
+pub trait CreditCheck: fmt::Debug + Send {
+    fn check(&self, order: &Order) -> Result<(), CreditError>;
+}
+
+/// Credit check that accepts every order
+#[derive(Debug, Default)]
+pub struct AlwaysPassCreditCheck;
+
+impl CreditCheck for AlwaysPassCreditCheck {
+    fn check(&self, _order: &Order) -> Result<(), CreditError> {
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
@@ -87,14 +252,371 @@ pub enum MinQuantityNotMetTypes {
     Bid,
 }
 
-#[derive(BorshSerialize, BorshDeserialize, Clone, Debug)]
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq)]
 pub struct CancelledOrder {
     cancel_request_type: CancelRequestType,
     order: Order,
 }
 
-#[derive(BorshSerialize, BorshDeserialize, Clone, Debug)]
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq)]
 pub enum MarketDataUpdate {
     Trade(Trade),
     Cancellation(CancelledOrder),
+    /// All updates from a single order request
+    Batch(Vec<MarketDataUpdate>),
+    /// An order request the orderbook refused, with the reason it bounced
+    OrderRejected {
+        id: Uuid,
+        reason: String,
+    },
+    /// Sent while the feed is idle, so consumers can tell a quiet
+    /// market from a dead publisher
+    Heartbeat {
+        /// Millisecond timestamp of when the heartbeat was sent
+        timestamp: i64,
+        /// Sequence of the last update sent before the heartbeat
+        last_sequence: u64,
+    },
+}
+
+/// Top of book, mid and spread are None unless both sides have orders
+#[derive(Copy, Clone, PartialEq, Debug, Serialize)]
+pub struct Quote {
+    pub best_bid: Option<Price>,
+    pub best_ask: Option<Price>,
+    pub mid_price: Option<f64>,
+    pub spread: Option<Price>,
+}
+
+/// Aggregate of the resting orders at one price
+#[derive(Copy, Clone, PartialEq, Debug, Serialize)]
+pub struct BookLevel {
+    pub side: OrderSide,
+    pub price: Price,
+    pub quantity: Quantity,
+    pub order_count: usize,
+}
+
+/// Where an order looked up by id currently stands
+///
+/// Filled, cancelled and expired orders are only reported until enough
+/// others have left the book after them, then they're NotFound, the
+/// same as an id that never existed
+#[derive(Copy, Clone, PartialEq, Debug, Serialize)]
+pub enum OrderStatus {
+    Resting,
+    PartiallyFilled,
+    Filled,
+    /// Cancelled by request, or an unfilled remainder that couldn't rest
+    Cancelled,
+    Expired,
+    NotFound,
+}
+
+/// An order's status, with its details while it's in the book
+/// or its final details once it's left
+#[derive(Copy, Clone, PartialEq, Debug, Serialize)]
+pub struct OrderStatusReport {
+    pub order_id: Uuid,
+    pub status: OrderStatus,
+    pub side: Option<OrderSide>,
+    pub price: Option<Price>,
+    pub initial_quantity: Option<Quantity>,
+    pub remaining_quantity: Option<Quantity>,
+}
+
+/// Resting orders captured at a point in time, in sequence order
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct BookSnapshot {
+    pub orders: Vec<Order>,
+}
+
+/// A difference in one order between two book snapshots
+#[derive(Clone, PartialEq, Debug)]
+pub enum BookDiff {
+    Added(Order),
+    Removed(Order),
+    Changed { before: Order, after: Order },
+}
+
+impl BookSnapshot {
+    /// How the book changed going from this snapshot to other
+    ///
+    /// Removed and changed orders come first in this snapshot's
+    /// sequence order, then added orders in other's
+    pub fn diff(&self, other: &BookSnapshot) -> Vec<BookDiff> {
+        let mut diffs: Vec<BookDiff> = self
+            .orders
+            .iter()
+            .filter_map(
+                |before| match other.orders.iter().find(|after| after.id == before.id) {
+                    Some(after) if after != before => Some(BookDiff::Changed {
+                        before: *before,
+                        after: *after,
+                    }),
+                    Some(_) => None,
+                    None => Some(BookDiff::Removed(*before)),
+                },
+            )
+            .collect();
+
+        diffs.extend(
+            other
+                .orders
+                .iter()
+                .filter(|after| !self.orders.iter().any(|before| before.id == after.id))
+                .map(|after| BookDiff::Added(*after)),
+        );
+        diffs
+    }
+}
+
+/// Aggregated price levels on each side of the book, best first
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct DepthSnapshot {
+    /// Highest price first
+    pub bids: Vec<BookLevel>,
+    /// Lowest price first
+    pub asks: Vec<BookLevel>,
+}
+
+/// An order request the orderbook refused
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct RejectedOrder {
+    pub order_id: Uuid,
+    pub reason: String,
+    /// Millisecond timestamp of the rejection
+    pub rejected_at: i64,
+}
+
+/// Progress of a single order through the book
+///
+/// Carries order owner private data, so it's published to internal
+/// subscribers only, separately from the public market data feed
+#[derive(Clone, PartialEq, Debug)]
+pub enum LifecycleEvent {
+    /// Passed validation and reached the book, before any matching
+    Accepted {
+        order_id: Uuid,
+    },
+    /// Remainder left resting in the book
+    Rested(Order),
+    PartiallyFilled {
+        order_id: Uuid,
+        filled_quantity: Quantity,
+        remaining_quantity: Quantity,
+    },
+    Filled {
+        order_id: Uuid,
+        filled_quantity: Quantity,
+    },
+    Cancelled(CancelledOrder),
+    Expired(CancelledOrder),
+    /// Quantity resting ahead of the order at its price changed
+    QueueUpdate {
+        order_id: Uuid,
+        volume_ahead: Quantity,
+    },
+    Rejected {
+        order_id: Uuid,
+        reason: String,
+    },
+}
+
+/// Price level with the age of its front order, for surveillance
+#[derive(Copy, Clone, PartialEq, Debug, Serialize)]
+pub struct LevelSummary {
+    pub level: BookLevel,
+    /// Milliseconds the front, oldest, order has rested
+    pub oldest_order_age_ms: i64,
+}
+
+/// What happens when an order would match a resting order of the same account
+#[derive(Copy, Clone, PartialEq, Debug, Default, Serialize)]
+pub enum SelfTradePrevention {
+    /// Orders of the same account match as any others
+    #[default]
+    None,
+    /// The resting order is passed over and left on the book, and any
+    /// remainder of the incoming order that would cross it is cancelled
+    SkipOpposing,
+    /// The resting order is cancelled and matching continues past it
+    CancelResting,
+}
+
+/// Most prices a price range can span, as its levels are allocated up front
+pub const MAX_PRICE_RANGE_TICKS: usize = 1 << 20;
+
+/// Bounded prices on a fixed tick, for books that keep
+/// their price levels in a flat array rather than a tree
+///
+/// Built through PriceRange::new, which rejects ranges an array can't cover
+#[derive(Copy, Clone, PartialEq, Debug, Serialize)]
+pub struct PriceRange {
+    min_price: Price,
+    max_price: Price,
+    tick_size: Price,
+}
+
+/// Reason a price range was rejected
+#[derive(Debug, PartialEq)]
+pub enum PriceRangeError {
+    /// Tick size is zero or negative
+    NonPositiveTickSize,
+    /// Max price is below min price
+    MaxBelowMin,
+    /// The range spans more than MAX_PRICE_RANGE_TICKS prices
+    TooManyTicks,
+}
+
+impl fmt::Display for PriceRangeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PriceRangeError::NonPositiveTickSize => write!(f, "Tick size must be positive"),
+            PriceRangeError::MaxBelowMin => write!(f, "Max price is below min price"),
+            PriceRangeError::TooManyTicks => write!(
+                f,
+                "Price range spans more than {} prices",
+                MAX_PRICE_RANGE_TICKS
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PriceRangeError {}
+
+impl PriceRange {
+    pub fn new(
+        min_price: Price,
+        max_price: Price,
+        tick_size: Price,
+    ) -> Result<Self, PriceRangeError> {
+        if tick_size <= 0 {
+            return Err(PriceRangeError::NonPositiveTickSize);
+        }
+
+        if max_price < min_price {
+            return Err(PriceRangeError::MaxBelowMin);
+        }
+
+        // A span too wide for Price is too wide for an array as well
+        match max_price.checked_sub(min_price) {
+            Some(span) if span / tick_size < MAX_PRICE_RANGE_TICKS as Price => Ok(Self {
+                min_price,
+                max_price,
+                tick_size,
+            }),
+            _ => Err(PriceRangeError::TooManyTicks),
+        }
+    }
+
+    pub fn min_price(&self) -> Price {
+        self.min_price
+    }
+
+    pub fn max_price(&self) -> Price {
+        self.max_price
+    }
+
+    pub fn tick_size(&self) -> Price {
+        self.tick_size
+    }
+
+    /// Whether price is within the range and on a tick
+    pub fn contains(&self, price: Price) -> bool {
+        (self.min_price..=self.max_price).contains(&price)
+            && (price - self.min_price) % self.tick_size == 0
+    }
+
+    /// Number of prices in the range, counting both ends
+    fn tick_count(&self) -> usize {
+        ((self.max_price - self.min_price) / self.tick_size) as usize + 1
+    }
+}
+
+/// When an order crosses an opposing price
+#[derive(Copy, Clone, PartialEq, Debug, Default, Serialize)]
+pub enum CrossRule {
+    /// Matches opposing orders at its price or better
+    #[default]
+    AtOrBetter,
+    /// Matches only opposing orders strictly better than its price
+    StrictlyBetter,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug, Default, Serialize)]
+pub struct OrderbookConfig {
+    /// Send all market data updates from an order request as a single batch
+    pub batch_market_data_updates: bool,
+    /// Largest price change allowed in a single modify
+    pub max_modify_price_change: Option<Price>,
+    /// Largest quantity change allowed in a single modify
+    pub max_modify_quantity_change: Option<Quantity>,
+    /// Modify in place, keeping priority, when only minimum quantity is lowered
+    pub keep_priority_on_minimum_quantity_decrease: bool,
+    /// Smallest price * quantity allowed for a single fill
+    pub min_notional: Option<Price>,
+    /// Reject an order that would neither trade nor rest, such as a kill
+    /// order whose fills were all discarded, instead of silently dropping it
+    pub reject_nothing_to_do: bool,
+    /// Milliseconds after creation during which only cancels are accepted,
+    /// unless the book is marked ready sooner
+    pub warmup_millis: Option<i64>,
+    /// Whether an order matches opposing orders at exactly its price
+    pub cross_rule: CrossRule,
+    /// Milliseconds during which resubmitting an identical order with
+    /// the same id is ignored rather than rejected as a duplicate id
+    pub duplicate_grace_millis: Option<i64>,
+    /// How orders of the same account are kept from trading with each other
+    pub self_trade_prevention: SelfTradePrevention,
+    /// Report consecutive fills between the same bid and ask as a single
+    /// trade, with their summed quantity at the quantity-weighted price
+    pub coalesce_fills: bool,
+    /// Keep price levels in a flat array over this range instead of a
+    /// tree, for dense prices, orders priced outside it are rejected
+    pub price_range: Option<PriceRange>,
+}
+
+/// Configuration a running orderbook is matching with
+#[derive(Copy, Clone, PartialEq, Debug, Serialize)]
+pub struct EffectiveConfig {
+    pub orderbook: OrderbookConfig,
+    /// Whether only cancels are accepted while the book warms up
+    pub warming_up: bool,
+}
+
+impl OrderbookConfig {
+    /// Whether an order on side at price can match an opposing order at opposing_price
+    fn crosses(&self, side: OrderSide, price: Price, opposing_price: Price) -> bool {
+        match (side, self.cross_rule) {
+            (OrderSide::Buy, CrossRule::AtOrBetter) => opposing_price <= price,
+            (OrderSide::Buy, CrossRule::StrictlyBetter) => opposing_price < price,
+            (OrderSide::Sell, CrossRule::AtOrBetter) => opposing_price >= price,
+            (OrderSide::Sell, CrossRule::StrictlyBetter) => opposing_price > price,
+        }
+    }
+
+    /// Whether order must not match opposing_order as both are from the same account
+    fn prevents_self_trade(&self, order: &Order, opposing_order: &Order) -> bool {
+        self.self_trade_prevention != SelfTradePrevention::None
+            && order.account_id.is_some()
+            && order.account_id == opposing_order.account_id
+    }
+
+    /// Whether an order at price can be accepted by a book with this price range
+    fn within_price_range(&self, price: Price) -> bool {
+        self.price_range
+            .is_none_or(|price_range| price_range.contains(price))
+    }
+
+    fn meets_min_notional(&self, price: Price, quantity: Quantity) -> bool {
+        match self.min_notional {
+            // Overflowing notional can't be below the minimum
+            Some(min_notional) => i64::try_from(quantity)
+                .ok()
+                .and_then(|quantity| price.checked_mul(quantity))
+                .is_none_or(|notional| notional >= min_notional),
+            None => true,
+        }
+    }
 }
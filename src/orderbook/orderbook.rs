@@ -1,27 +1,67 @@
-use std::{cmp::min, collections::HashMap};
+use std::{
+    cmp::min,
+    collections::{HashMap, VecDeque},
+    time::Instant,
+};
 
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, Result};
 use chrono::Utc;
-use crossbeam::channel::Sender;
+use crossbeam::channel::{self, Receiver, Sender};
 use uuid::Uuid;
 
 use crate::{
-    metrics::{MATCHING_DURATION, ORDERS_FILLED_COUNTER, ORDER_COUNTER, TRADE_COUNTER},
+    metrics::{
+        EFFECTIVE_SPREAD, EMPTY_MATCH_PASSES, EMPTY_PRICE_LEVELS_SKIPPED, MATCHING_DURATION,
+        ORDERS_FILLED_COUNTER, ORDER_COUNTER, TRADE_COUNTER,
+    },
     orderbook::CancelledOrder,
-    web_server::{CancelRequestType, OrderRequest},
+    web_server::{CancelOrderResult, CancelRequestType, OrderRequest},
 };
 
 use super::{
+    checked_quantity_sub,
     orderlevels::{AskOrderLevels, BidOrderLevels, OrderLevels},
-    MarketDataUpdate, Order, OrderSide, OrderType, Trade, TradeInfo,
+    AlwaysPassCreditCheck, BookLevel, BookSnapshot, CreditCheck, DepthSnapshot, EffectiveConfig,
+    LevelSummary, LifecycleEvent, MarketDataUpdate, MatchError, Order, OrderSide, OrderStatus,
+    OrderStatusReport, OrderType, OrderbookConfig, Price, ProcessTradeError, Quantity, Quote,
+    RejectedOrder, SelfTradePrevention, Trade, TradeAudit, TradeInfo,
 };
 
+const TRADE_HISTORY_CAPACITY: usize = 1000;
+const REJECTION_HISTORY_CAPACITY: usize = 100;
+const TERMINAL_STATUS_CAPACITY: usize = 10_000;
+
 #[derive(Debug)]
 pub struct Orderbook {
     ask_levels: AskOrderLevels,
     bid_levels: BidOrderLevels,
     orders: HashMap<Uuid, Order>,
+    trade_history: VecDeque<Trade>,
+    rejection_history: VecDeque<RejectedOrder>,
     market_data_update_sender: Option<Sender<MarketDataUpdate>>,
+    lifecycle_event_senders: Vec<Sender<LifecycleEvent>>,
+    config: OrderbookConfig,
+    manual_time: Option<i64>,
+    warmup_ends_at: Option<i64>,
+    next_sequence: u64,
+    /// Both legs of each one-cancels-other pair, mapped to each other
+    oco_links: HashMap<Uuid, Uuid>,
+    /// New orders accepted within the duplicate grace window, oldest
+    /// first, with when they were accepted
+    recent_submissions: VecDeque<(i64, Order)>,
+    /// Resting orders cancelled for self trade prevention by the
+    /// current match, until they're added to its market updates
+    self_trade_cancellations: Vec<CancelledOrder>,
+    credit_check: Box<dyn CreditCheck>,
+    /// Final status and details of orders that left the book, the
+    /// oldest evicted past TERMINAL_STATUS_CAPACITY
+    terminal_statuses: HashMap<Uuid, (OrderStatus, Order)>,
+    /// Ids in terminal_statuses, oldest first
+    terminal_status_ids: VecDeque<Uuid>,
+    /// Caps the quantity of each fill, so tests can match one
+    /// resting order more than once in a pass
+    #[cfg(test)]
+    max_fill_quantity: Option<Quantity>,
 }
 
 impl Default for Orderbook {
@@ -32,14 +72,96 @@ impl Default for Orderbook {
 
 impl Orderbook {
     pub fn new(market_data_update_sender: Option<Sender<MarketDataUpdate>>) -> Self {
+        Self::with_config(market_data_update_sender, OrderbookConfig::default())
+    }
+
+    pub fn with_config(
+        market_data_update_sender: Option<Sender<MarketDataUpdate>>,
+        config: OrderbookConfig,
+    ) -> Self {
+        let warmup_ends_at = config
+            .warmup_millis
+            .map(|warmup_millis| Utc::now().timestamp_millis() + warmup_millis);
+
+        let (ask_levels, bid_levels) = match config.price_range {
+            Some(price_range) => (
+                AskOrderLevels::with_price_range(price_range),
+                BidOrderLevels::with_price_range(price_range),
+            ),
+            None => (AskOrderLevels::new(), BidOrderLevels::new()),
+        };
+
         Self {
-            ask_levels: AskOrderLevels::new(),
-            bid_levels: BidOrderLevels::new(),
+            ask_levels,
+            bid_levels,
             orders: HashMap::new(),
+            trade_history: VecDeque::with_capacity(TRADE_HISTORY_CAPACITY),
+            rejection_history: VecDeque::with_capacity(REJECTION_HISTORY_CAPACITY),
             market_data_update_sender,
+            lifecycle_event_senders: vec![],
+            config,
+            manual_time: None,
+            warmup_ends_at,
+            next_sequence: 1,
+            oco_links: HashMap::new(),
+            recent_submissions: VecDeque::new(),
+            self_trade_cancellations: vec![],
+            credit_check: Box::new(AlwaysPassCreditCheck),
+            terminal_statuses: HashMap::new(),
+            terminal_status_ids: VecDeque::new(),
+            #[cfg(test)]
+            max_fill_quantity: None,
+        }
+    }
+
+    /// Rebuilds an orderbook from the resting orders of a snapshot
+    ///
+    /// Orders keep their sequence, so matching priority is
+    /// exactly as it was when the snapshot was taken
+    ///
+    /// Fails with MatchError::PriceOutOfRange if an order is
+    /// priced outside the config's price range
+    pub fn restore(
+        market_data_update_sender: Option<Sender<MarketDataUpdate>>,
+        config: OrderbookConfig,
+        mut orders: Vec<Order>,
+    ) -> Result<Self> {
+        let mut orderbook = Self::with_config(market_data_update_sender, config);
+        orders.sort_by_key(|order| order.sequence);
+        for order in orders {
+            orderbook.next_sequence = orderbook.next_sequence.max(order.sequence + 1);
+            orderbook.rest_order(order)?;
+        }
+        Ok(orderbook)
+    }
+
+    /// Replaces the credit check every new order must pass before matching
+    pub fn set_credit_check(&mut self, credit_check: Box<dyn CreditCheck>) {
+        self.credit_check = credit_check;
+    }
+
+    /// Subscribes an internal consumer, such as settlement or
+    /// auditing, to the lifecycle events of every order
+    pub fn subscribe_lifecycle_events(&mut self) -> Receiver<LifecycleEvent> {
+        let (sender, receiver) = channel::unbounded();
+        self.lifecycle_event_senders.push(sender);
+        receiver
+    }
+
+    /// Captures the resting orders, to diff against a later capture
+    pub fn book_snapshot(&self) -> BookSnapshot {
+        BookSnapshot {
+            orders: self.snapshot(),
         }
     }
 
+    /// Every resting order, in sequence order
+    pub fn snapshot(&self) -> Vec<Order> {
+        let mut orders: Vec<Order> = self.orders.values().copied().collect();
+        orders.sort_by_key(|order| order.sequence);
+        orders
+    }
+
     /// Matches and handles trade request
     ///
     /// Only pub access to orderbook
@@ -50,62 +172,748 @@ impl Orderbook {
         &mut self,
         order_request: OrderRequest,
     ) -> Result<Vec<MarketDataUpdate>> {
+        // Checked before the request touches the book, so a failed
+        // check can't leave applied updates unpublished
+        if cfg!(debug_assertions) {
+            self.assert_virtual_equals_remaining();
+        }
+
+        if self.in_warmup() {
+            if let Some(order_ids) = placed_order_ids(&order_request) {
+                let market_updates: Vec<MarketDataUpdate> = order_ids
+                    .into_iter()
+                    .map(|order_id| self.rejected(order_id, MatchError::Warmup.into()))
+                    .collect();
+                self.publish_market_data_updates(&market_updates);
+                return Ok(market_updates);
+            }
+        }
+
         let market_updates: Vec<MarketDataUpdate> = match order_request {
-            OrderRequest::Trade(trade_request) => match trade_request.try_into() {
-                Ok(order) => match self.match_order(order) {
-                    Ok(trades) => trades.into_iter().map(MarketDataUpdate::Trade).collect(),
-                    Err(_) => vec![],
-                },
-                Err(_) => vec![],
-            },
+            OrderRequest::Trade(trade_request) => {
+                let order_id = trade_request.id;
+                match trade_request.try_into() {
+                    Ok(order) => self.trade_market_updates(order),
+                    Err(error) => vec![self.rejected(order_id, error)],
+                }
+            }
             OrderRequest::Cancel(cancel_request_type, order_id) => {
                 match self.cancel_order(cancel_request_type, order_id) {
                     Some(cancelled_order) => vec![MarketDataUpdate::Cancellation(cancelled_order)],
                     None => vec![],
                 }
             }
-            OrderRequest::Modify(trade_request) => match trade_request.try_into() {
-                Ok(order) => match self.modify_order(order) {
-                    Ok((cancelled_order, trades)) => {
-                        let mut updates = vec![MarketDataUpdate::Cancellation(cancelled_order)];
-                        updates.extend(trades.into_iter().map(MarketDataUpdate::Trade));
-                        updates
+            OrderRequest::Modify(trade_request) => {
+                let order_id = trade_request.id;
+                match trade_request.try_into() {
+                    Ok(order) => self.modify_market_updates(order),
+                    Err(error) => vec![self.rejected(order_id, error)],
+                }
+            }
+            OrderRequest::SafeReprice(trade_request) => {
+                let order_id = trade_request.id;
+                match trade_request.try_into() {
+                    Ok(order) => self.safe_reprice_market_updates(order),
+                    Err(error) => vec![self.rejected(order_id, error)],
+                }
+            }
+            OrderRequest::Replace(original_order_id, trade_request) => {
+                let order_id = trade_request.id;
+                match trade_request.try_into() {
+                    Ok(order) => self.replace_market_updates(original_order_id, order),
+                    Err(error) => vec![self.rejected(order_id, error)],
+                }
+            }
+            OrderRequest::Upsert(trade_request) => {
+                let order_id = trade_request.id;
+                match Order::try_from(trade_request) {
+                    Ok(order) if self.orders.contains_key(&order.id) => {
+                        self.modify_market_updates(order)
                     }
-                    Err(_) => vec![],
-                },
-                Err(_) => vec![],
+                    Ok(order) => self.trade_market_updates(order),
+                    Err(error) => vec![self.rejected(order_id, error)],
+                }
+            }
+            OrderRequest::Oco(first_trade_request, second_trade_request) => {
+                let first_order_id = first_trade_request.id;
+                let second_order_id = second_trade_request.id;
+                match (
+                    Order::try_from(first_trade_request),
+                    Order::try_from(second_trade_request),
+                ) {
+                    (Ok(first_order), Ok(second_order)) => {
+                        self.oco_market_updates(first_order, second_order)
+                    }
+                    (Err(error), _) => vec![self.rejected(first_order_id, error)],
+                    (_, Err(error)) => vec![self.rejected(second_order_id, error)],
+                }
+            }
+            OrderRequest::RecentTrades(limit, reply_sender) => {
+                let _ = reply_sender.send(self.recent_trades(limit));
+                vec![]
+            }
+            OrderRequest::Book(reply_sender) => {
+                let _ = reply_sender.send(self.snapshot());
+                vec![]
+            }
+            OrderRequest::RecentRejections(limit, reply_sender) => {
+                let _ = reply_sender.send(self.recent_rejections(limit));
+                vec![]
+            }
+            OrderRequest::Config(reply_sender) => {
+                let _ = reply_sender.send(self.effective_config());
+                vec![]
+            }
+            OrderRequest::Quote(reply_sender) => {
+                let _ = reply_sender.send(self.quote());
+                vec![]
+            }
+            OrderRequest::Query(order_id, reply_sender) => {
+                let _ = reply_sender.send(self.order_status(order_id));
+                vec![]
+            }
+            OrderRequest::Snapshot(levels, reply_sender) => {
+                let _ = reply_sender.send(self.depth_snapshot(levels));
+                vec![]
+            }
+            OrderRequest::BookHistogram(limit, reply_sender) => {
+                let _ = reply_sender.send(self.book_histogram(limit));
+                vec![]
+            }
+            OrderRequest::CancelMany(order_ids, reply_sender) => {
+                let mut cancel_results = Vec::with_capacity(order_ids.len());
+                let mut updates = vec![];
+                for order_id in order_ids {
+                    let cancelled_order = self.cancel_order(CancelRequestType::User, order_id);
+                    cancel_results.push(CancelOrderResult {
+                        order_id,
+                        found: cancelled_order.is_some(),
+                    });
+                    updates.extend(cancelled_order.map(MarketDataUpdate::Cancellation));
+                }
+                let _ = reply_sender.send(cancel_results);
+                updates
+            }
+            OrderRequest::CancelLevel(cancel_level_request, reply_sender) => {
+                // Publishes its own cancellation updates
+                let cancelled_orders = self.cancel_level(
+                    cancel_level_request.side,
+                    cancel_level_request.price,
+                    cancel_level_request.account_id,
+                );
+                let _ = reply_sender.send(
+                    cancelled_orders
+                        .iter()
+                        .map(|cancelled_order| cancelled_order.order.id)
+                        .collect(),
+                );
+                vec![]
+            }
+        };
+
+        self.publish_market_data_updates(&market_updates);
+
+        Ok(market_updates)
+    }
+
+    /// Panics if any resting order's virtual remaining quantity wasn't
+    /// settled back to its remaining quantity once matching finished
+    fn assert_virtual_equals_remaining(&self) {
+        for order in self.orders.values() {
+            assert_eq!(
+                order.virtual_remaining_quantity, order.remaining_quantity,
+                "Virtual remaining quantity diverged for order {}",
+                order.id
+            );
+        }
+    }
+
+    fn trade_market_updates(&mut self, order: Order) -> Vec<MarketDataUpdate> {
+        if self.is_duplicate_submission(&order) {
+            return vec![];
+        }
+
+        let order_id = order.id;
+        match self.match_order(order) {
+            Ok(trades) => {
+                self.record_submission(order);
+                self.trades_with_oco_cancellations(trades)
+            }
+            Err(error) => vec![self.rejected(order_id, error)],
+        }
+    }
+
+    /// Whether order is an identical retry of an order accepted within
+    /// the duplicate grace window, which is then treated as already done
+    ///
+    /// A reused id with any different parameters isn't a duplicate, so
+    /// it's still rejected by matching
+    fn is_duplicate_submission(&mut self, order: &Order) -> bool {
+        let Some(duplicate_grace_millis) = self.config.duplicate_grace_millis else {
+            return false;
+        };
+
+        let now = self.now_millis();
+        while self
+            .recent_submissions
+            .front()
+            .is_some_and(|(accepted_at, _)| now - accepted_at > duplicate_grace_millis)
+        {
+            self.recent_submissions.pop_front();
+        }
+
+        self.recent_submissions.iter().any(|(_, submitted_order)| {
+            submitted_order.id == order.id
+                && submitted_order.type_ == order.type_
+                && submitted_order.side == order.side
+                && submitted_order.price == order.price
+                && submitted_order.initial_quantity == order.initial_quantity
+                && submitted_order.minimum_quantity == order.minimum_quantity
+                && submitted_order.min_fill_ratio == order.min_fill_ratio
+                && submitted_order.account_id == order.account_id
+        })
+    }
+
+    fn record_submission(&mut self, order: Order) {
+        if self.config.duplicate_grace_millis.is_some() {
+            let accepted_at = self.now_millis();
+            self.recent_submissions.push_back((accepted_at, order));
+        }
+    }
+
+    /// Places both legs of a one-cancels-other pair, first leg first
+    ///
+    /// Any fill on a leg, partial or full, cancels the other leg. If
+    /// the first leg fills on entry the second is rejected unplaced
+    fn oco_market_updates(
+        &mut self,
+        first_order: Order,
+        second_order: Order,
+    ) -> Vec<MarketDataUpdate> {
+        if first_order.id == second_order.id
+            || self.orders.contains_key(&first_order.id)
+            || self.orders.contains_key(&second_order.id)
+        {
+            return [first_order.id, second_order.id]
+                .into_iter()
+                .map(|order_id| {
+                    self.rejected(
+                        order_id,
+                        anyhow!("OCO order ids must be distinct and unused"),
+                    )
+                })
+                .collect();
+        }
+
+        self.oco_links.insert(first_order.id, second_order.id);
+        self.oco_links.insert(second_order.id, first_order.id);
+
+        let mut updates = self.trade_market_updates(first_order);
+        if self.oco_links.contains_key(&second_order.id) {
+            updates.extend(self.trade_market_updates(second_order));
+        } else {
+            updates.push(self.rejected(second_order.id, anyhow!("OCO sibling filled")));
+        }
+        updates
+    }
+
+    /// Trade updates followed by cancellations of resting orders for self
+    /// trade prevention, then of the siblings of any OCO legs that traded
+    fn trades_with_oco_cancellations(&mut self, trades: Vec<Trade>) -> Vec<MarketDataUpdate> {
+        let mut cancellations: Vec<MarketDataUpdate> = self
+            .self_trade_cancellations
+            .drain(..)
+            .map(MarketDataUpdate::Cancellation)
+            .collect();
+        for trade in &trades {
+            for order_id in [trade.bid.order_id, trade.ask.order_id] {
+                if let Some(sibling_order_id) = self.unlink_oco(&order_id) {
+                    cancellations.extend(
+                        self.cancel_order(CancelRequestType::OcoSiblingFilled, sibling_order_id)
+                            .map(MarketDataUpdate::Cancellation),
+                    );
+                }
+            }
+        }
+
+        let mut updates: Vec<MarketDataUpdate> =
+            trades.into_iter().map(MarketDataUpdate::Trade).collect();
+        updates.extend(cancellations);
+        updates
+    }
+
+    /// Removes the OCO link between an order and its sibling, returning the sibling
+    fn unlink_oco(&mut self, order_id: &Uuid) -> Option<Uuid> {
+        let sibling_order_id = self.oco_links.remove(order_id)?;
+        self.oco_links.remove(&sibling_order_id);
+        Some(sibling_order_id)
+    }
+
+    fn modify_market_updates(&mut self, order: Order) -> Vec<MarketDataUpdate> {
+        if self.config.keep_priority_on_minimum_quantity_decrease
+            && self.lower_minimum_quantity_in_place(&order)
+        {
+            return vec![];
+        }
+        let order_id = order.id;
+        let replacement = self.modify_order(order);
+        self.replacement_market_updates(order_id, replacement)
+    }
+
+    /// Modifies like post only, rejecting rather than trading if the
+    /// new price would cross, which leaves the original order resting
+    fn safe_reprice_market_updates(&mut self, order: Order) -> Vec<MarketDataUpdate> {
+        if self.orders.contains_key(&order.id) && self.can_match_order(&order) {
+            return vec![self.rejected(order.id, anyhow!("Safe reprice would cross"))];
+        }
+        self.modify_market_updates(order)
+    }
+
+    fn replace_market_updates(
+        &mut self,
+        original_order_id: Uuid,
+        order: Order,
+    ) -> Vec<MarketDataUpdate> {
+        let order_id = order.id;
+        let replacement = self.replace_order(original_order_id, order);
+        self.replacement_market_updates(order_id, replacement)
+    }
+
+    fn replacement_market_updates(
+        &mut self,
+        order_id: Uuid,
+        replacement: Result<(CancelledOrder, Vec<Trade>)>,
+    ) -> Vec<MarketDataUpdate> {
+        match replacement {
+            Ok((cancelled_order, trades)) => {
+                let mut updates = vec![MarketDataUpdate::Cancellation(cancelled_order)];
+                updates.extend(self.trades_with_oco_cancellations(trades));
+                updates
+            }
+            Err(error) => vec![self.rejected(order_id, error)],
+        }
+    }
+
+    /// Records a rejection in the rejection history and
+    /// builds its market data update
+    fn rejected(&mut self, order_id: Uuid, error: anyhow::Error) -> MarketDataUpdate {
+        let reason = error.to_string();
+
+        if self.rejection_history.len() == REJECTION_HISTORY_CAPACITY {
+            self.rejection_history.pop_front();
+        }
+        self.rejection_history.push_back(RejectedOrder {
+            order_id,
+            reason: reason.clone(),
+            rejected_at: self.now_millis(),
+        });
+        self.publish_lifecycle_event(LifecycleEvent::Rejected {
+            order_id,
+            reason: reason.clone(),
+        });
+
+        MarketDataUpdate::OrderRejected {
+            id: order_id,
+            reason,
+        }
+    }
+
+    /// Cancels all resting orders at a price level,
+    /// only those of account_id if given
+    ///
+    /// Sends a cancellation market update for each
+    /// cancelled order
+    pub fn cancel_level(
+        &mut self,
+        side: OrderSide,
+        price: Price,
+        account_id: Option<Uuid>,
+    ) -> Vec<CancelledOrder> {
+        let order_ids: Vec<Uuid> = match side {
+            OrderSide::Buy => self.bid_levels.get_orders(&price),
+            OrderSide::Sell => self.ask_levels.get_orders(&price),
+        }
+        .map(|orders| {
+            orders
+                .iter()
+                .filter(|order_id| {
+                    account_id.is_none()
+                        || self.orders.get(order_id).map(|order| order.account_id)
+                            == Some(account_id)
+                })
+                .copied()
+                .collect()
+        })
+        .unwrap_or_default();
+
+        self.cancel_orders(order_ids)
+    }
+
+    /// Cancels all resting orders matching the predicate
+    ///
+    /// Sends a cancellation market update for each
+    /// cancelled order
+    pub fn cancel_where(&mut self, pred: impl Fn(&Order) -> bool) -> Vec<CancelledOrder> {
+        let order_ids: Vec<Uuid> = self
+            .orders
+            .values()
+            .filter(|order| pred(order))
+            .map(|order| order.id)
+            .collect();
+
+        self.cancel_orders(order_ids)
+    }
+
+    fn cancel_orders(&mut self, order_ids: Vec<Uuid>) -> Vec<CancelledOrder> {
+        let cancelled_orders: Vec<CancelledOrder> = order_ids
+            .into_iter()
+            .filter_map(|order_id| self.cancel_order(CancelRequestType::MassCancel, order_id))
+            .collect();
+
+        let market_updates: Vec<MarketDataUpdate> = cancelled_orders
+            .iter()
+            .cloned()
+            .map(MarketDataUpdate::Cancellation)
+            .collect();
+        self.publish_market_data_updates(&market_updates);
+
+        cancelled_orders
+    }
+
+    /// Most recent trades, newest first
+    ///
+    /// Only the last TRADE_HISTORY_CAPACITY trades are retained
+    pub fn recent_trades(&self, limit: usize) -> Vec<Trade> {
+        self.trade_history
+            .iter()
+            .rev()
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    /// Most recent rejections, newest first
+    ///
+    /// Only the last REJECTION_HISTORY_CAPACITY rejections are retained
+    pub fn recent_rejections(&self, limit: usize) -> Vec<RejectedOrder> {
+        self.rejection_history
+            .iter()
+            .rev()
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    /// Retained trades between two orders, oldest first
+    ///
+    /// Either order may be the bid, only the last
+    /// TRADE_HISTORY_CAPACITY trades are searched
+    pub fn trades_between(&self, a: Uuid, b: Uuid) -> Vec<Trade> {
+        self.trade_history
+            .iter()
+            .filter(|trade| {
+                (trade.bid.order_id == a && trade.ask.order_id == b)
+                    || (trade.bid.order_id == b && trade.ask.order_id == a)
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Best bid and best ask prices
+    pub fn get_bbo(&self) -> (Option<Price>, Option<Price>) {
+        (
+            self.bid_levels.get_best_price().copied(),
+            self.ask_levels.get_best_price().copied(),
+        )
+    }
+
+    pub fn quote(&self) -> Quote {
+        let (best_bid, best_ask) = self.get_bbo();
+
+        let (mid_price, spread) = match (best_bid, best_ask) {
+            (Some(bid), Some(ask)) => (Some((bid + ask) as f64 / 2.0), Some(ask - bid)),
+            _ => (None, None),
+        };
+
+        Quote {
+            best_bid,
+            best_ask,
+            mid_price,
+            spread,
+        }
+    }
+
+    /// Every occupied price level, bids then asks, best first
+    ///
+    /// At most limit levels are returned for each side
+    pub fn book_histogram(&self, limit: usize) -> Vec<BookLevel> {
+        let mut book_levels = self.side_book_levels(OrderSide::Buy, limit);
+        book_levels.extend(self.side_book_levels(OrderSide::Sell, limit));
+        book_levels
+    }
+
+    /// Status of an order by id, see OrderStatus for what NotFound covers
+    pub fn order_status(&self, order_id: Uuid) -> OrderStatusReport {
+        let (status, order) = match self.orders.get(&order_id) {
+            Some(order) if order.remaining_quantity < order.initial_quantity => {
+                (OrderStatus::PartiallyFilled, Some(order))
+            }
+            Some(order) => (OrderStatus::Resting, Some(order)),
+            None => match self.terminal_statuses.get(&order_id) {
+                Some((status, order)) => (*status, Some(order)),
+                None => (OrderStatus::NotFound, None),
             },
         };
 
+        OrderStatusReport {
+            order_id,
+            status,
+            side: order.map(|order| order.side),
+            price: order.map(|order| order.price),
+            initial_quantity: order.map(|order| order.initial_quantity),
+            remaining_quantity: order.map(|order| order.remaining_quantity),
+        }
+    }
+
+    /// Aggregated levels on each side, up to levels deep
+    pub fn depth_snapshot(&self, levels: usize) -> DepthSnapshot {
+        DepthSnapshot {
+            bids: self.side_book_levels(OrderSide::Buy, levels),
+            asks: self.side_book_levels(OrderSide::Sell, levels),
+        }
+    }
+
+    /// Every occupied price level, bids then asks, best first,
+    /// with how long its front order has rested
+    pub fn level_summary(&self) -> Vec<LevelSummary> {
+        let now = self.now_millis();
+
+        self.book_histogram(usize::MAX)
+            .into_iter()
+            .filter_map(|level| {
+                let front_order_id = match level.side {
+                    OrderSide::Buy => self.bid_levels.get_order(level.price, 0),
+                    OrderSide::Sell => self.ask_levels.get_order(level.price, 0),
+                }?;
+                let front_order = self.orders.get(front_order_id)?;
+
+                Some(LevelSummary {
+                    level,
+                    oldest_order_age_ms: now - front_order.inserted_at,
+                })
+            })
+            .collect()
+    }
+
+    fn side_book_levels(&self, side: OrderSide, limit: usize) -> Vec<BookLevel> {
+        let prices = match side {
+            OrderSide::Buy => self.bid_levels.get_prices(),
+            OrderSide::Sell => self.ask_levels.get_prices(),
+        };
+
+        prices
+            .into_iter()
+            .take(limit)
+            .filter_map(|price| {
+                let order_ids = match side {
+                    OrderSide::Buy => self.bid_levels.get_orders(price),
+                    OrderSide::Sell => self.ask_levels.get_orders(price),
+                }?;
+
+                Some(BookLevel {
+                    side,
+                    price: *price,
+                    quantity: order_ids
+                        .iter()
+                        .filter_map(|order_id| self.orders.get(order_id))
+                        .map(|order| order.remaining_quantity)
+                        .sum(),
+                    order_count: order_ids.len(),
+                })
+            })
+            .collect()
+    }
+
+    /// Fraction of quantity that would fill immediately at price
+    ///
+    /// Walks crossing opposing levels in priority order, skipping
+    /// resting orders whose minimum quantity wouldn't be met
+    pub fn fill_estimate(&self, side: OrderSide, price: Price, quantity: Quantity) -> f64 {
+        if quantity == 0 {
+            return 0.0;
+        }
+
+        let crossing_prices: Vec<&Price> = match side {
+            OrderSide::Buy => self
+                .ask_levels
+                .get_prices()
+                .into_iter()
+                .take_while(|ask_price| self.config.crosses(side, price, **ask_price))
+                .collect(),
+            OrderSide::Sell => self
+                .bid_levels
+                .get_prices()
+                .into_iter()
+                .take_while(|bid_price| self.config.crosses(side, price, **bid_price))
+                .collect(),
+        };
+
+        let mut unfilled_quantity = quantity;
+        for crossing_price in crossing_prices {
+            let opposing_orders = match side {
+                OrderSide::Buy => self.ask_levels.get_orders(crossing_price),
+                OrderSide::Sell => self.bid_levels.get_orders(crossing_price),
+            };
+
+            for opposing_order_id in opposing_orders.into_iter().flatten() {
+                if unfilled_quantity == 0 {
+                    break;
+                }
+
+                let opposing_order = self
+                    .orders
+                    .get(opposing_order_id)
+                    .expect("Order should never be in price level but not in orders");
+
+                let fill_quantity = min(unfilled_quantity, opposing_order.remaining_quantity);
+                if fill_quantity < opposing_order.minimum_quantity
+                    || !self
+                        .config
+                        .meets_min_notional(*crossing_price, fill_quantity)
+                {
+                    continue;
+                }
+                unfilled_quantity -= fill_quantity;
+            }
+        }
+
+        (quantity - unfilled_quantity) as f64 / quantity as f64
+    }
+
+    /// Ends the warmup period early
+    pub fn mark_ready(&mut self) {
+        self.warmup_ends_at = None;
+    }
+
+    pub fn effective_config(&self) -> EffectiveConfig {
+        EffectiveConfig {
+            orderbook: self.config,
+            warming_up: self.in_warmup(),
+        }
+    }
+
+    fn in_warmup(&self) -> bool {
+        self.warmup_ends_at
+            .is_some_and(|warmup_ends_at| self.now_millis() < warmup_ends_at)
+    }
+
+    /// Fixes the book's clock to a millisecond timestamp
+    ///
+    /// Once set, orders are stamped on arrival at the book rather
+    /// than on receipt, so matching no longer depends on wall time
+    pub fn set_time(&mut self, timestamp_millis: i64) {
+        self.manual_time = Some(timestamp_millis);
+    }
+
+    fn now_millis(&self) -> i64 {
+        self.manual_time
+            .unwrap_or_else(|| Utc::now().timestamp_millis())
+    }
+
+    fn publish_market_data_updates(&self, market_updates: &[MarketDataUpdate]) {
         if let Some(sender) = &self.market_data_update_sender {
-            for market_data_update in &market_updates {
+            if self.config.batch_market_data_updates {
+                if !market_updates.is_empty() {
+                    let _ = sender.send(MarketDataUpdate::Batch(market_updates.to_vec()));
+                }
+                return;
+            }
+
+            for market_data_update in market_updates {
                 let _ = sender.send(market_data_update.clone());
             }
         }
+    }
+
+    fn publish_lifecycle_event(&self, lifecycle_event: LifecycleEvent) {
+        for sender in &self.lifecycle_event_senders {
+            let _ = sender.send(lifecycle_event.clone());
+        }
+    }
 
-        Ok(market_updates)
+    fn publish_fill(
+        &self,
+        order_id: Uuid,
+        filled_quantity: Quantity,
+        remaining_quantity: Quantity,
+    ) {
+        self.publish_lifecycle_event(match remaining_quantity {
+            0 => LifecycleEvent::Filled {
+                order_id,
+                filled_quantity,
+            },
+            _ => LifecycleEvent::PartiallyFilled {
+                order_id,
+                filled_quantity,
+                remaining_quantity,
+            },
+        });
     }
 
+    /// Matches order against resting orders, resting any remainder
+    ///
+    /// Rejects an order whose id is already resting, which guards
+    /// against a client accidentally resubmitting an order and
+    /// crossing its own resting order. This only covers colliding
+    /// ids, account level self trade prevention is configured by
+    /// OrderbookConfig::self_trade_prevention
+    ///
+    /// Rejects an order failing the credit check with MatchError::Credit
     fn match_order(&mut self, mut order: Order) -> Result<Vec<Trade>> {
         ORDER_COUNTER.inc();
 
+        if let Some(manual_time) = self.manual_time {
+            order.inserted_at = manual_time;
+        }
+
         if self.orders.contains_key(&order.id) {
             bail!("Order id already in use")
         }
 
+        self.check_order_acceptable(&order)?;
+
+        self.publish_lifecycle_event(LifecycleEvent::Accepted { order_id: order.id });
+
         let trades = match self.can_match_order(&order) {
             true => {
-                let start_time = Utc::now().timestamp();
+                let start_time = Instant::now();
                 let trades = self.internal_match_order(&mut order);
-                let end_time = Utc::now().timestamp();
-                MATCHING_DURATION.observe((end_time - start_time) as f64);
+                MATCHING_DURATION.observe(start_time.elapsed().as_secs_f64());
                 trades
             }
             false => vec![],
         };
 
-        if order.type_ == OrderType::Normal && order.remaining_quantity > 0 {
-            self.insert_order(order)
+        // Only normal orders rest, and not across their own account's orders
+        let rests = order.type_ == OrderType::Normal
+            && order.remaining_quantity > 0
+            && !self.crosses_own_order(&order);
+
+        // An order that neither traded nor rests did nothing
+        if self.config.reject_nothing_to_do
+            && trades.is_empty()
+            && self.self_trade_cancellations.is_empty()
+            && !rests
+        {
+            return Err(MatchError::NothingToDo.into());
+        }
+
+        if rests {
+            self.insert_order(order)?;
+        } else if order.remaining_quantity == 0 {
+            self.record_terminal_status(OrderStatus::Filled, order);
+        } else {
+            self.record_terminal_status(OrderStatus::Cancelled, order);
         }
 
         if order.remaining_quantity == 0 {
@@ -115,24 +923,79 @@ impl Orderbook {
         Ok(trades)
     }
 
+    /// Checks every new order must pass before matching, other than its id
+    ///
+    /// Rejects an order priced outside the price range with MatchError::PriceOutOfRange
+    /// and one failing the credit check with MatchError::Credit
+    fn check_order_acceptable(&self, order: &Order) -> Result<()> {
+        // Market orders ignore their price, so any price is fine
+        if order.type_ != OrderType::Market && !self.config.within_price_range(order.price) {
+            return Err(MatchError::PriceOutOfRange.into());
+        }
+
+        if let Err(credit_error) = self.credit_check.check(order) {
+            return Err(MatchError::Credit(credit_error).into());
+        }
+
+        Ok(())
+    }
+
     fn can_match_order(&self, order: &Order) -> bool {
-        match order.side {
-            OrderSide::Buy => {
-                if let Some(best_opposing_price) = self.ask_levels.get_best_price() {
-                    return best_opposing_price <= &order.price;
-                }
-            }
-            OrderSide::Sell => {
-                if let Some(best_opposing_price) = self.bid_levels.get_best_price() {
-                    return best_opposing_price >= &order.price;
-                }
-            }
+        let best_opposing_price = match order.side {
+            OrderSide::Buy => self.ask_levels.get_best_price(),
+            OrderSide::Sell => self.bid_levels.get_best_price(),
+        };
+
+        best_opposing_price
+            .is_some_and(|best_opposing_price| self.crosses(order, *best_opposing_price))
+    }
+
+    /// Whether order crosses a resting order of its own account, which
+    /// SelfTradePrevention::SkipOpposing passed over rather than traded with
+    ///
+    /// Its remainder is cancelled rather than rested, as resting
+    /// would leave the book locked or crossed
+    fn crosses_own_order(&self, order: &Order) -> bool {
+        if self.config.self_trade_prevention != SelfTradePrevention::SkipOpposing {
+            return false;
         }
-        false
+
+        let price_levels = match order.side {
+            OrderSide::Buy => self.ask_levels.get_prices(),
+            OrderSide::Sell => self.bid_levels.get_prices(),
+        };
+        price_levels
+            .into_iter()
+            .take_while(|price_level| self.crosses(order, **price_level))
+            .any(|price_level| {
+                let opposing_orders = match order.side {
+                    OrderSide::Buy => self.ask_levels.get_orders(price_level),
+                    OrderSide::Sell => self.bid_levels.get_orders(price_level),
+                };
+                opposing_orders.is_some_and(|opposing_orders| {
+                    opposing_orders.iter().any(|opposing_order_id| {
+                        self.orders
+                            .get(opposing_order_id)
+                            .is_some_and(|opposing_order| {
+                                self.config.prevents_self_trade(order, opposing_order)
+                            })
+                    })
+                })
+            })
+    }
+
+    /// Whether order can match an opposing order at opposing_price,
+    /// market orders cross any price
+    fn crosses(&self, order: &Order, opposing_price: Price) -> bool {
+        order.type_ == OrderType::Market
+            || self.config.crosses(order.side, order.price, opposing_price)
     }
 
     fn internal_match_order(&mut self, order: &mut Order) -> Vec<Trade> {
         let mut trades = vec![];
+        let mut self_trade_cancellations = vec![];
+        let matched_at = self.now_millis();
+        let arrival_mid_price = self.quote().mid_price;
 
         let price_levels = match order.side {
             OrderSide::Buy => self.ask_levels.get_prices(),
@@ -144,13 +1007,23 @@ impl Orderbook {
                 break;
             }
 
+            // Levels are in priority order, so no later level crosses either
+            if !self.crosses(order, *price_level) {
+                break;
+            }
+
             let opposing_orders = match order.side {
                 OrderSide::Buy => self.ask_levels.get_orders(price_level),
                 OrderSide::Sell => self.bid_levels.get_orders(price_level),
             };
 
             if let Some(opposing_orders) = opposing_orders {
-                for opposing_order_id in opposing_orders {
+                if opposing_orders.is_empty() {
+                    EMPTY_PRICE_LEVELS_SKIPPED.inc();
+                    continue;
+                }
+
+                for (queue_position, opposing_order_id) in opposing_orders.iter().enumerate() {
                     if order.virtual_remaining_quantity == 0 {
                         break;
                     }
@@ -160,21 +1033,45 @@ impl Orderbook {
                         .get_mut(opposing_order_id)
                         .expect("Order should never be in price level but not in orders");
 
+                    if self.config.prevents_self_trade(order, opposing_order) {
+                        if self.config.self_trade_prevention == SelfTradePrevention::CancelResting {
+                            self_trade_cancellations.push(*opposing_order_id);
+                        }
+                        continue;
+                    }
+
                     let quantity = min(
                         order.virtual_remaining_quantity,
                         opposing_order.virtual_remaining_quantity,
                     );
+                    #[cfg(test)]
+                    let quantity = self
+                        .max_fill_quantity
+                        .map_or(quantity, |max_fill_quantity| {
+                            min(quantity, max_fill_quantity)
+                        });
 
-                    if quantity < opposing_order.minimum_quantity {
+                    // A resting minimum is checked against what the incoming order has
+                    // left after the fills ahead of it in priority, a resting order it
+                    // can't satisfy is passed over and keeps its place for a later order
+                    if quantity == 0 || quantity < opposing_order.minimum_quantity {
+                        continue;
+                    }
+
+                    if !self.config.meets_min_notional(*price_level, quantity) {
                         continue;
                     }
 
                     order.virtual_remaining_quantity -= quantity;
                     opposing_order.virtual_remaining_quantity -= quantity;
 
+                    // Market orders have no limit, they pay the resting price
                     let order_trade_info = TradeInfo {
                         order_id: order.id,
-                        price: order.price,
+                        price: match order.type_ {
+                            OrderType::Market => *price_level,
+                            _ => order.price,
+                        },
                         quantity,
                     };
 
@@ -184,14 +1081,28 @@ impl Orderbook {
                         quantity,
                     };
 
+                    let resting_order_audit = TradeAudit {
+                        queue_position,
+                        resting_duration_ms: matched_at - opposing_order.inserted_at,
+                    };
+
+                    let effective_spread = arrival_mid_price
+                        .map(|mid_price| 2.0 * (*price_level as f64 - mid_price).abs());
+
                     let trade = match order.side {
                         OrderSide::Buy => Trade {
                             bid: order_trade_info,
                             ask: opposing_order_trade_info,
+                            aggressor_side: order.side,
+                            resting_order_audit,
+                            effective_spread,
                         },
                         OrderSide::Sell => Trade {
                             bid: opposing_order_trade_info,
                             ask: order_trade_info,
+                            aggressor_side: order.side,
+                            resting_order_audit,
+                            effective_spread,
                         },
                     };
 
@@ -200,15 +1111,55 @@ impl Orderbook {
             }
         }
 
-        if (order.initial_quantity - order.virtual_remaining_quantity) >= order.minimum_quantity {
-            self.commit_trades(order, &trades);
-            trades
+        let filled_quantity =
+            match checked_quantity_sub(order.initial_quantity, order.virtual_remaining_quantity) {
+                Ok(filled_quantity) => filled_quantity,
+                Err(_) => {
+                    self.discard_trades(order, &trades);
+                    return vec![];
+                }
+            };
+        let meets_kill_fill_threshold =
+            order.type_ != OrderType::Kill || filled_quantity >= order.kill_fill_threshold();
+        if filled_quantity >= order.minimum_quantity && meets_kill_fill_threshold {
+            // Coalesced before committing, so the trade history
+            // records the same trades that are published
+            let trades = if self.config.coalesce_fills {
+                coalesce_fills(trades)
+            } else {
+                trades
+            };
+            match self.commit_trades(order, &trades) {
+                // Resting orders are only cancelled for self trade prevention
+                // when the match takes effect, a discarded match leaves them be
+                Ok(()) => {
+                    self.cancel_self_trade_orders(self_trade_cancellations);
+                    trades
+                }
+                Err(_) => {
+                    self.discard_trades(order, &trades);
+                    vec![]
+                }
+            }
         } else {
+            EMPTY_MATCH_PASSES.inc();
             self.discard_trades(order, &trades);
             vec![]
         }
     }
 
+    /// Cancels resting orders that would have traded with their own account,
+    /// holding the cancellations for the match's market updates
+    fn cancel_self_trade_orders(&mut self, order_ids: Vec<Uuid>) {
+        let cancelled_orders: Vec<CancelledOrder> = order_ids
+            .into_iter()
+            .filter_map(|order_id| {
+                self.cancel_order(CancelRequestType::SelfTradePrevention, order_id)
+            })
+            .collect();
+        self.self_trade_cancellations.extend(cancelled_orders);
+    }
+
     fn discard_trades(&mut self, order: &mut Order, trades: &Vec<Trade>) {
         for trade in trades {
             let opposing_order_id = match order.side {
@@ -226,7 +1177,54 @@ impl Orderbook {
         order.virtual_remaining_quantity = order.remaining_quantity
     }
 
-    fn commit_trades(&mut self, order: &mut Order, trades: &Vec<Trade>) {
+    /// Resets the state a request leaves behind if it panics part way
+    /// through matching, so the next request starts from the committed book
+    pub(crate) fn discard_uncommitted(&mut self) {
+        for order in self.orders.values_mut() {
+            order.virtual_remaining_quantity = order.remaining_quantity;
+        }
+        self.self_trade_cancellations.clear();
+    }
+
+    /// Applies trades to the book
+    ///
+    /// Trades are checked to be balanced before any are applied,
+    /// so an unbalanced trade leaves the book untouched
+    fn commit_trades(
+        &mut self,
+        order: &mut Order,
+        trades: &Vec<Trade>,
+    ) -> Result<(), ProcessTradeError> {
+        if trades
+            .iter()
+            .any(|trade| trade.bid.quantity != trade.ask.quantity)
+        {
+            return Err(ProcessTradeError::FillQuantityHigherThanRemaining);
+        }
+
+        // Also checked up front, so the running remaining below can't underflow
+        trades
+            .iter()
+            .try_fold(order.remaining_quantity, |remaining, trade| {
+                checked_quantity_sub(remaining, trade.bid.quantity)
+            })?;
+
+        let mut touched_levels: Vec<(OrderSide, Price)> = vec![];
+        for trade in trades {
+            let level = match order.side {
+                OrderSide::Buy => (OrderSide::Sell, trade.ask.price),
+                OrderSide::Sell => (OrderSide::Buy, trade.bid.price),
+            };
+            if !touched_levels.contains(&level) {
+                touched_levels.push(level);
+            }
+        }
+        let volumes_ahead_before: Vec<Vec<(Uuid, Quantity)>> = touched_levels
+            .iter()
+            .map(|(side, price)| self.queue_volumes_ahead(*side, *price))
+            .collect();
+
+        let mut order_remaining_quantity = order.remaining_quantity;
         for trade in trades {
             let opposing_order_id = match order.side {
                 OrderSide::Buy => trade.ask.order_id,
@@ -239,6 +1237,7 @@ impl Orderbook {
                 .expect("Order shouldn't have been removed yet");
 
             opposing_order.remaining_quantity = opposing_order.virtual_remaining_quantity;
+            let opposing_remaining_quantity = opposing_order.remaining_quantity;
 
             if opposing_order.remaining_quantity == 0 {
                 ORDERS_FILLED_COUNTER.inc();
@@ -251,22 +1250,168 @@ impl Orderbook {
                         .remove_order(&trade.ask.price, &opposing_order_id),
                 };
 
-                self.orders.remove(&opposing_order_id);
+                if let Some(filled_order) = self.orders.remove(&opposing_order_id) {
+                    self.record_terminal_status(OrderStatus::Filled, filled_order);
+                }
             }
+            order_remaining_quantity -= trade.bid.quantity;
+            self.publish_fill(
+                opposing_order_id,
+                trade.bid.quantity,
+                opposing_remaining_quantity,
+            );
+            self.publish_fill(order.id, trade.bid.quantity, order_remaining_quantity);
+
             TRADE_COUNTER.inc();
+            if let Some(effective_spread) = trade.effective_spread {
+                EFFECTIVE_SPREAD.observe(effective_spread);
+            }
+            self.record_trade(trade.clone());
         }
 
         order.remaining_quantity = order.virtual_remaining_quantity;
         self.ask_levels.remove_empty_levels();
         self.bid_levels.remove_empty_levels();
+
+        for ((side, price), volumes_ahead) in touched_levels.into_iter().zip(volumes_ahead_before) {
+            self.publish_queue_updates(side, price, &volumes_ahead);
+        }
+        Ok(())
+    }
+
+    /// Each order at a price level with the quantity resting ahead
+    /// of it, in queue order
+    ///
+    /// Empty without lifecycle subscribers, since only queue
+    /// updates need it
+    fn queue_volumes_ahead(&self, side: OrderSide, price: Price) -> Vec<(Uuid, Quantity)> {
+        if self.lifecycle_event_senders.is_empty() {
+            return vec![];
+        }
+
+        let order_ids = match side {
+            OrderSide::Buy => self.bid_levels.get_orders(&price),
+            OrderSide::Sell => self.ask_levels.get_orders(&price),
+        };
+
+        let mut volume_ahead = 0;
+        order_ids
+            .into_iter()
+            .flatten()
+            .filter_map(|order_id| {
+                let order = self.orders.get(order_id)?;
+                let order_volume_ahead = (*order_id, volume_ahead);
+                volume_ahead += order.remaining_quantity;
+                Some(order_volume_ahead)
+            })
+            .collect()
+    }
+
+    /// Publishes a queue update for every order still at the level
+    /// whose volume ahead differs from volumes_ahead_before
+    fn publish_queue_updates(
+        &self,
+        side: OrderSide,
+        price: Price,
+        volumes_ahead_before: &[(Uuid, Quantity)],
+    ) {
+        for (order_id, volume_ahead) in self.queue_volumes_ahead(side, price) {
+            let changed =
+                volumes_ahead_before
+                    .iter()
+                    .any(|(before_order_id, before_volume_ahead)| {
+                        *before_order_id == order_id && *before_volume_ahead != volume_ahead
+                    });
+            if changed {
+                self.publish_lifecycle_event(LifecycleEvent::QueueUpdate {
+                    order_id,
+                    volume_ahead,
+                });
+            }
+        }
     }
 
-    fn insert_order(&mut self, order: Order) {
+    fn record_trade(&mut self, trade: Trade) {
+        if self.trade_history.len() == TRADE_HISTORY_CAPACITY {
+            self.trade_history.pop_front();
+        }
+        self.trade_history.push_back(trade);
+    }
+
+    /// Keeps the final status of an order leaving the book,
+    /// replacing any earlier one under the same id
+    fn record_terminal_status(&mut self, status: OrderStatus, order: Order) {
+        if self
+            .terminal_statuses
+            .insert(order.id, (status, order))
+            .is_some()
+        {
+            return;
+        }
+
+        if self.terminal_status_ids.len() == TERMINAL_STATUS_CAPACITY {
+            if let Some(evicted_order_id) = self.terminal_status_ids.pop_front() {
+                self.terminal_statuses.remove(&evicted_order_id);
+            }
+        }
+        self.terminal_status_ids.push_back(order.id);
+    }
+
+    fn insert_order(&mut self, mut order: Order) -> Result<(), MatchError> {
+        order.sequence = self.next_sequence;
+        self.next_sequence += 1;
+        self.rest_order(order)?;
+        self.publish_lifecycle_event(LifecycleEvent::Rested(order));
+        Ok(())
+    }
+
+    /// Leaves the book untouched if the order's price can't be held
+    fn rest_order(&mut self, order: Order) -> Result<(), MatchError> {
         match order.side {
             OrderSide::Buy => self.bid_levels.insert_order(order.price, order.id),
             OrderSide::Sell => self.ask_levels.insert_order(order.price, order.id),
-        }
+        }?;
         self.orders.insert(order.id, order);
+        Ok(())
+    }
+
+    /// Rests an order in its price level without tracking it in orders,
+    /// leaving the book inconsistent so matching against it panics
+    #[cfg(test)]
+    pub(crate) fn insert_untracked_level_order(&mut self, order: Order) {
+        match order.side {
+            OrderSide::Buy => self.bid_levels.insert_order(order.price, order.id),
+            OrderSide::Sell => self.ask_levels.insert_order(order.price, order.id),
+        }
+        .expect("Untracked order should be within the price range");
+    }
+
+    /// Lowers a resting order's minimum quantity without it losing priority
+    ///
+    /// Only applies when minimum quantity is the sole change and is
+    /// decreasing. Falls back to a regular modify if the order could
+    /// now cross, so it gets matched
+    fn lower_minimum_quantity_in_place(&mut self, order: &Order) -> bool {
+        let existing_order = match self.orders.get(&order.id) {
+            Some(existing_order) => existing_order,
+            None => return false,
+        };
+
+        let only_minimum_quantity_decreased = existing_order.type_ == order.type_
+            && existing_order.side == order.side
+            && existing_order.price == order.price
+            && existing_order.initial_quantity == order.initial_quantity
+            && existing_order.account_id == order.account_id
+            && order.minimum_quantity < existing_order.minimum_quantity;
+
+        if !only_minimum_quantity_decreased || self.can_match_order(existing_order) {
+            return false;
+        }
+
+        if let Some(existing_order) = self.orders.get_mut(&order.id) {
+            existing_order.minimum_quantity = order.minimum_quantity;
+        }
+        true
     }
 
     /// Modifies an order, equivalent to cancel + add
@@ -275,26 +1420,95 @@ impl Orderbook {
     ///
     /// Doesn't modify in place, cancels, and adds new order
     ///
-    /// Quantity of new order is abs(modified_new_order - old_order)
+    /// Remaining quantity of new order is the new quantity less
+    /// whatever the original order had already filled
+    ///
+    /// Minimum quantity is taken from the modify request and can't
+    /// exceed the remaining quantity. As with any modify, the order
+    /// loses its queue priority
     fn modify_order(&mut self, order: Order) -> Result<(CancelledOrder, Vec<Trade>)> {
-        let existing_order = match self.orders.get(&order.id) {
+        self.replace_order(order.id, order)
+    }
+
+    /// Cancels an order and replaces it with an order under a new id
+    ///
+    /// Same rules as modify, the cancellation references the
+    /// original id and the replacement uses the new id
+    fn replace_order(
+        &mut self,
+        original_order_id: Uuid,
+        order: Order,
+    ) -> Result<(CancelledOrder, Vec<Trade>)> {
+        let existing_order = match self.orders.get(&original_order_id) {
             Some(existing) => existing,
             None => bail!("Order not found"),
         };
 
+        if order.id != original_order_id && self.orders.contains_key(&order.id) {
+            bail!("Order id already in use")
+        }
+
         if existing_order.type_ != order.type_ {
             bail!("Cannot modify order type")
         }
 
-        if (existing_order.initial_quantity - existing_order.remaining_quantity)
-            > order.initial_quantity
+        if existing_order.side != order.side {
+            bail!("Cannot modify order side")
+        }
+
+        if existing_order.account_id != order.account_id {
+            bail!("Cannot modify order account")
+        }
+
+        let filled_quantity = checked_quantity_sub(
+            existing_order.initial_quantity,
+            existing_order.remaining_quantity,
+        )?;
+        let remaining_quantity = match checked_quantity_sub(order.initial_quantity, filled_quantity)
         {
-            bail!("Cannot modify quantity to lower than currently filled")
+            Ok(remaining_quantity) => remaining_quantity,
+            Err(_) => bail!("Cannot modify quantity to lower than currently filled"),
+        };
+
+        if order.minimum_quantity > remaining_quantity {
+            bail!("Cannot modify minimum quantity to higher than remaining")
+        }
+
+        if let Some(max_price_change) = self.config.max_modify_price_change {
+            if (order.price - existing_order.price).abs() > max_price_change {
+                bail!("Price change exceeds modify limit")
+            }
+        }
+
+        if let Some(max_quantity_change) = self.config.max_modify_quantity_change {
+            if order
+                .initial_quantity
+                .abs_diff(existing_order.initial_quantity)
+                > max_quantity_change
+            {
+                bail!("Quantity change exceeds modify limit")
+            }
         }
 
+        // Checked before the original is cancelled, so failing leaves the
+        // original order resting. Only normal orders rest and the type can't
+        // change, so the replacement can't be rejected as nothing to do
+        self.check_order_acceptable(&order)?;
+
         let cancelled_order = self
-            .cancel_order(CancelRequestType::Internal, order.id)
-            .ok_or_else(|| anyhow::anyhow!("Could not cancel order"))?;
+            .cancel_order(CancelRequestType::Replace, original_order_id)
+            .ok_or_else(|| anyhow!("Could not cancel order"))?;
+
+        if let Some(sibling_order_id) = self.unlink_oco(&original_order_id) {
+            self.oco_links.insert(order.id, sibling_order_id);
+            self.oco_links.insert(sibling_order_id, order.id);
+        }
+
+        // Lowering quantity to exactly the filled amount completes the
+        // order, so it's removed rather than rested with nothing left
+        if remaining_quantity == 0 {
+            return Ok((cancelled_order, vec![]));
+        }
 
         let fresh_order = Order {
             type_: order.type_,
@@ -302,11 +1516,15 @@ impl Orderbook {
             side: order.side,
             price: order.price,
             initial_quantity: order.initial_quantity,
-            remaining_quantity: cancelled_order.order.remaining_quantity,
-            virtual_remaining_quantity: cancelled_order.order.remaining_quantity,
-            minimum_quantity: cancelled_order.order.minimum_quantity,
+            remaining_quantity,
+            virtual_remaining_quantity: remaining_quantity,
+            minimum_quantity: order.minimum_quantity,
+            inserted_at: order.inserted_at,
+            sequence: 0,
+            min_fill_ratio: order.min_fill_ratio,
+            account_id: order.account_id,
         };
-        let trades = self.match_order(fresh_order).unwrap_or_default();
+        let trades = self.match_order(fresh_order)?;
         Ok((cancelled_order, trades))
     }
 
@@ -315,6 +1533,12 @@ impl Orderbook {
         cancel_request_type: CancelRequestType,
         order_id: Uuid,
     ) -> Option<CancelledOrder> {
+        let volumes_ahead_before = self
+            .orders
+            .get(&order_id)
+            .map(|order| self.queue_volumes_ahead(order.side, order.price))
+            .unwrap_or_default();
+
         if let Some(order) = self.orders.remove(&order_id) {
             let price = order.price;
             let cancelled = match order.side {
@@ -323,10 +1547,24 @@ impl Orderbook {
             };
 
             if cancelled {
-                return Some(CancelledOrder {
+                if cancel_request_type != CancelRequestType::Replace {
+                    self.unlink_oco(&order_id);
+                }
+                let cancelled_order = CancelledOrder {
                     cancel_request_type,
                     order,
+                };
+                self.publish_lifecycle_event(match cancelled_order.cancel_request_type {
+                    CancelRequestType::Expiry => LifecycleEvent::Expired(cancelled_order.clone()),
+                    _ => LifecycleEvent::Cancelled(cancelled_order.clone()),
                 });
+                self.publish_queue_updates(order.side, price, &volumes_ahead_before);
+                let status = match cancelled_order.cancel_request_type {
+                    CancelRequestType::Expiry => OrderStatus::Expired,
+                    _ => OrderStatus::Cancelled,
+                };
+                self.record_terminal_status(status, order);
+                return Some(cancelled_order);
             }
         }
 
@@ -334,9 +1572,72 @@ impl Orderbook {
     }
 }
 
+/// Ids of the orders a request would place, None for requests
+/// that don't place orders, such as cancels and queries
+fn placed_order_ids(order_request: &OrderRequest) -> Option<Vec<Uuid>> {
+    match order_request {
+        OrderRequest::Trade(trade_request)
+        | OrderRequest::Modify(trade_request)
+        | OrderRequest::SafeReprice(trade_request)
+        | OrderRequest::Replace(_, trade_request)
+        | OrderRequest::Upsert(trade_request) => Some(vec![trade_request.id]),
+        OrderRequest::Oco(first_trade_request, second_trade_request) => {
+            Some(vec![first_trade_request.id, second_trade_request.id])
+        }
+        _ => None,
+    }
+}
+
+/// Merges consecutive trades between the same bid and ask, summing their
+/// quantities at the quantity-weighted price of each side. The merged
+/// trade keeps the first fill's audit and weights its effective spread
+fn coalesce_fills(trades: Vec<Trade>) -> Vec<Trade> {
+    let mut coalesced_trades: Vec<Trade> = Vec::with_capacity(trades.len());
+    for trade in trades {
+        match coalesced_trades.last_mut() {
+            Some(previous_trade)
+                if previous_trade.bid.order_id == trade.bid.order_id
+                    && previous_trade.ask.order_id == trade.ask.order_id =>
+            {
+                let previous_quantity = previous_trade.bid.quantity;
+                previous_trade.effective_spread = previous_trade
+                    .effective_spread
+                    .zip(trade.effective_spread)
+                    .map(|(previous_spread, spread)| {
+                        (previous_spread * previous_quantity as f64
+                            + spread * trade.bid.quantity as f64)
+                            / (previous_quantity + trade.bid.quantity) as f64
+                    });
+                coalesce_trade_info(&mut previous_trade.bid, &trade.bid);
+                coalesce_trade_info(&mut previous_trade.ask, &trade.ask);
+            }
+            _ => coalesced_trades.push(trade),
+        }
+    }
+    coalesced_trades
+}
+
+/// Adds a fill to one side of a coalesced trade, rounding the weighted price down
+fn coalesce_trade_info(trade_info: &mut TradeInfo, fill: &TradeInfo) {
+    let quantity = trade_info.quantity + fill.quantity;
+    let notional = trade_info.price as i128 * trade_info.quantity as i128
+        + fill.price as i128 * fill.quantity as i128;
+    trade_info.price = (notional / quantity as i128) as Price;
+    trade_info.quantity = quantity;
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::orderbook::{Price, Quantity};
+    use crossbeam::channel;
+    use tokio::sync::oneshot;
+
+    use prometheus::core::Metric;
+
+    use crate::{
+        metrics::{reset_metrics, METRICS_TEST_LOCK},
+        orderbook::{BookDiff, CreditError, CrossRule, PriceRange, PriceRangeError, Quantity},
+        web_server::{CancelLevelRequest, TradeRequest},
+    };
 
     use super::*;
 
@@ -346,6 +1647,23 @@ mod tests {
         assert_eq!(trade.ask, ask)
     }
 
+    /// Order as it's stored once rested with the given sequence
+    fn rested(order: Order, sequence: u64) -> Order {
+        Order { sequence, ..order }
+    }
+
+    /// An orderbook on each price level backend, tree first, for tests comparing them
+    fn level_backend_orderbooks() -> [Orderbook; 2] {
+        let array_config = OrderbookConfig {
+            price_range: Some(PriceRange::new(0, 100, 1).unwrap()),
+            ..Default::default()
+        };
+        [
+            Orderbook::default(),
+            Orderbook::with_config(None, array_config),
+        ]
+    }
+
     fn assert_empty_book(orderbook: &Orderbook) {
         assert!(orderbook.orders.is_empty());
         assert!(orderbook.ask_levels.get_prices().is_empty());
@@ -445,6 +1763,121 @@ mod tests {
         assert_empty_book(&orderbook);
     }
 
+    #[test]
+    fn immediate_or_cancel_partially_fills_and_cancels_remainder() {
+        let mut orderbook = Orderbook::default();
+
+        let first_ask = Order::new(OrderType::Normal, OrderSide::Sell, 1, 1, 0);
+        let second_ask = Order::new(OrderType::Normal, OrderSide::Sell, 2, 2, 0);
+        orderbook.match_order(first_ask).unwrap();
+        orderbook.match_order(second_ask).unwrap();
+
+        let buy_order = Order::new(OrderType::ImmediateOrCancel, OrderSide::Buy, 2, 5, 0);
+        let trades = orderbook.match_order(buy_order).unwrap();
+
+        let filled_quantity: Quantity = trades.iter().map(|trade| trade.bid.quantity).sum();
+        assert_eq!(trades.len(), 2);
+        assert_eq!(filled_quantity, 3);
+        assert_empty_book(&orderbook);
+    }
+
+    #[test]
+    fn unfillable_fill_or_kill_leaves_book_untouched() {
+        let mut orderbook = Orderbook::default();
+
+        let first_ask = Order::new(OrderType::Normal, OrderSide::Sell, 1, 1, 0);
+        let second_ask = Order::new(OrderType::Normal, OrderSide::Sell, 2, 2, 0);
+        orderbook.match_order(first_ask).unwrap();
+        orderbook.match_order(second_ask).unwrap();
+        let snapshot = orderbook.snapshot();
+
+        let buy_order = Order::new(OrderType::Kill, OrderSide::Buy, 2, 5, 0);
+        let trades = orderbook.match_order(buy_order).unwrap();
+
+        assert!(trades.is_empty());
+        assert_eq!(orderbook.snapshot(), snapshot);
+        assert!(orderbook.recent_trades(1).is_empty());
+    }
+
+    #[test]
+    fn market_buy_sweeps_ask_levels_at_resting_prices() {
+        let mut orderbook = Orderbook::default();
+
+        let asks: Vec<Order> = [10, 11, 12]
+            .into_iter()
+            .map(|price| Order::new(OrderType::Normal, OrderSide::Sell, price, 1, 0))
+            .collect();
+        for ask in &asks {
+            orderbook.match_order(*ask).unwrap();
+        }
+
+        let buy_order = Order::new(OrderType::Market, OrderSide::Buy, 0, 4, 0);
+        let trades = orderbook.match_order(buy_order).unwrap();
+
+        assert_eq!(trades.len(), 3);
+        for (index, ask) in asks.iter().enumerate() {
+            assert_trade(
+                &trades,
+                index,
+                TradeInfo {
+                    order_id: buy_order.id,
+                    price: ask.price,
+                    quantity: 1,
+                },
+                TradeInfo {
+                    order_id: ask.id,
+                    price: ask.price,
+                    quantity: 1,
+                },
+            );
+        }
+        assert_empty_book(&orderbook);
+    }
+
+    fn kill_order_with_min_fill_ratio(min_fill_ratio: f64) -> Order {
+        Order {
+            min_fill_ratio: Some(min_fill_ratio),
+            ..Order::new(OrderType::Kill, OrderSide::Buy, 2, 5, 0)
+        }
+    }
+
+    #[test]
+    fn kill_order_meeting_min_fill_ratio_commits_fills() {
+        let mut orderbook = Orderbook::default();
+        orderbook
+            .match_order(Order::new(OrderType::Normal, OrderSide::Sell, 1, 1, 0))
+            .unwrap();
+        orderbook
+            .match_order(Order::new(OrderType::Normal, OrderSide::Sell, 2, 2, 0))
+            .unwrap();
+
+        let trades = orderbook
+            .match_order(kill_order_with_min_fill_ratio(0.6))
+            .unwrap();
+
+        assert_eq!(trades.len(), 2);
+        assert_empty_book(&orderbook);
+    }
+
+    #[test]
+    fn kill_order_missing_min_fill_ratio_discards_fills() {
+        let mut orderbook = Orderbook::default();
+        orderbook
+            .match_order(Order::new(OrderType::Normal, OrderSide::Sell, 1, 1, 0))
+            .unwrap();
+        orderbook
+            .match_order(Order::new(OrderType::Normal, OrderSide::Sell, 2, 2, 0))
+            .unwrap();
+        let snapshot = orderbook.snapshot();
+
+        let trades = orderbook
+            .match_order(kill_order_with_min_fill_ratio(0.8))
+            .unwrap();
+
+        assert!(trades.is_empty());
+        assert_eq!(orderbook.snapshot(), snapshot);
+    }
+
     #[test]
     fn can_match_symmetric_opposing_orders() {
         let mut orderbook = Orderbook::default();
@@ -458,8 +1891,9 @@ mod tests {
         let second_trades = orderbook.match_order(sell_order).unwrap();
 
         assert!(first_trades.is_empty());
+        let trade = second_trades.first().unwrap();
         assert_eq!(
-            second_trades.first().unwrap(),
+            trade,
             &Trade {
                 bid: TradeInfo {
                     order_id: buy_order.id,
@@ -470,12 +1904,49 @@ mod tests {
                     order_id: sell_order.id,
                     price,
                     quantity,
-                }
+                },
+                aggressor_side: OrderSide::Sell,
+                resting_order_audit: TradeAudit {
+                    queue_position: 0,
+                    resting_duration_ms: trade.resting_order_audit.resting_duration_ms,
+                },
+                effective_spread: None,
             }
         );
         assert_empty_book(&orderbook);
     }
 
+    #[test]
+    fn effective_spread_measured_from_arrival_mid() {
+        let mut orderbook = Orderbook::new(None);
+
+        let bid = Order::new(OrderType::Normal, OrderSide::Buy, 9, 1, 0);
+        let near_ask = Order::new(OrderType::Normal, OrderSide::Sell, 11, 1, 0);
+        let far_ask = Order::new(OrderType::Normal, OrderSide::Sell, 12, 1, 0);
+        for order in [bid, near_ask, far_ask] {
+            orderbook.match_order(order).unwrap();
+        }
+
+        let buy_order = Order::new(OrderType::Kill, OrderSide::Buy, 12, 2, 0);
+        let trades = orderbook.match_order(buy_order).unwrap();
+
+        let effective_spreads: Vec<Option<f64>> =
+            trades.iter().map(|trade| trade.effective_spread).collect();
+        assert_eq!(effective_spreads, vec![Some(2.0), Some(4.0)]);
+    }
+
+    #[test]
+    fn effective_spread_none_for_one_sided_book() {
+        let mut orderbook = Orderbook::new(None);
+
+        let sell_order = Order::new(OrderType::Normal, OrderSide::Sell, 1, 1, 0);
+        let buy_order = Order::new(OrderType::Normal, OrderSide::Buy, 1, 1, 0);
+        orderbook.match_order(sell_order).unwrap();
+        let trades = orderbook.match_order(buy_order).unwrap();
+
+        assert_eq!(trades[0].effective_spread, None);
+    }
+
     #[test]
     fn can_partially_fill_orders() {
         let mut orderbook = Orderbook::default();
@@ -506,6 +1977,49 @@ mod tests {
         assert_book_has_order(&orderbook, &sell_order.id, &sell_order.side, &1, &price);
     }
 
+    fn exact_price_cross_trades(cross_rule: CrossRule) -> bool {
+        let config = OrderbookConfig {
+            cross_rule,
+            ..Default::default()
+        };
+        let mut orderbook = Orderbook::with_config(None, config);
+        let price = 10;
+
+        let sell_order = Order::new(OrderType::Normal, OrderSide::Sell, price, 1, 0);
+        let buy_order = Order::new(OrderType::Normal, OrderSide::Buy, price, 1, 0);
+        orderbook.match_order(sell_order).unwrap();
+
+        !orderbook.match_order(buy_order).unwrap().is_empty()
+    }
+
+    #[test]
+    fn exact_price_cross_depends_on_cross_rule() {
+        assert!(exact_price_cross_trades(CrossRule::AtOrBetter));
+        assert!(!exact_price_cross_trades(CrossRule::StrictlyBetter));
+    }
+
+    #[test]
+    fn strictly_better_matches_only_improving_levels() {
+        let config = OrderbookConfig {
+            cross_rule: CrossRule::StrictlyBetter,
+            ..Default::default()
+        };
+        let mut orderbook = Orderbook::with_config(None, config);
+
+        let better_ask = Order::new(OrderType::Normal, OrderSide::Sell, 9, 1, 0);
+        let equal_ask = Order::new(OrderType::Normal, OrderSide::Sell, 10, 1, 0);
+        orderbook.match_order(better_ask).unwrap();
+        orderbook.match_order(equal_ask).unwrap();
+
+        let buy_order = Order::new(OrderType::ImmediateOrCancel, OrderSide::Buy, 10, 2, 0);
+        let trades = orderbook.match_order(buy_order).unwrap();
+
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].ask.order_id, better_ask.id);
+        assert_book_has_order(&orderbook, &equal_ask.id, &equal_ask.side, &1, &10);
+        assert_eq!(orderbook.fill_estimate(OrderSide::Buy, 10, 1), 0.0);
+    }
+
     #[test]
     fn can_match_orders_with_different_prices() {
         let mut orderbook = Orderbook::default();
@@ -600,6 +2114,45 @@ mod tests {
         assert_book_has_order(&orderbook, &sell_order.id, &sell_order.side, &2, &price);
     }
 
+    fn nothing_to_do_rejecting_orderbook() -> Orderbook {
+        let config = OrderbookConfig {
+            reject_nothing_to_do: true,
+            ..Default::default()
+        };
+        Orderbook::with_config(None, config)
+    }
+
+    fn is_nothing_to_do_error(result: Result<Vec<Trade>>) -> bool {
+        result.is_err_and(|error| {
+            error.downcast_ref::<MatchError>() == Some(&MatchError::NothingToDo)
+        })
+    }
+
+    #[test]
+    fn kill_order_with_discarded_fills_rejected_as_nothing_to_do() {
+        let mut orderbook = nothing_to_do_rejecting_orderbook();
+        let price = 1;
+
+        let sell_order = Order::new(OrderType::Normal, OrderSide::Sell, price, 1, 0);
+        let kill_order = Order::new(OrderType::Kill, OrderSide::Buy, price, 2, 2);
+        orderbook.match_order(sell_order).unwrap();
+
+        assert!(is_nothing_to_do_error(orderbook.match_order(kill_order)));
+        assert_book_has_order(&orderbook, &sell_order.id, &sell_order.side, &1, &price);
+    }
+
+    #[test]
+    fn kill_order_against_empty_book_rejected_as_nothing_to_do() {
+        let mut orderbook = nothing_to_do_rejecting_orderbook();
+        let kill_order = Order::new(OrderType::Kill, OrderSide::Buy, 1, 1, 0);
+
+        assert!(is_nothing_to_do_error(orderbook.match_order(kill_order)));
+        assert!(Orderbook::default()
+            .match_order(kill_order)
+            .is_ok_and(|trades| trades.is_empty()));
+        assert_empty_book(&orderbook);
+    }
+
     #[test]
     fn order_filled_when_min_quantity_met() {
         let mut orderbook = Orderbook::default();
@@ -674,6 +2227,63 @@ mod tests {
         assert_empty_asks(&orderbook);
     }
 
+    #[test]
+    fn large_aggressor_fills_every_resting_minimum() {
+        let mut orderbook = Orderbook::default();
+        let price = 1;
+
+        let sell_orders = [
+            Order::new(OrderType::Normal, OrderSide::Sell, price, 3, 3),
+            Order::new(OrderType::Normal, OrderSide::Sell, price, 4, 2),
+            Order::new(OrderType::Normal, OrderSide::Sell, price, 5, 5),
+        ];
+        for sell_order in sell_orders {
+            orderbook.match_order(sell_order).unwrap();
+        }
+        let buy_order = Order::new(OrderType::Normal, OrderSide::Buy, price, 100, 0);
+
+        let trades = orderbook.match_order(buy_order).unwrap();
+
+        assert_eq!(trades.len(), sell_orders.len());
+        for (trade, sell_order) in trades.iter().zip(sell_orders) {
+            assert_eq!(trade.ask.order_id, sell_order.id);
+            assert_eq!(trade.ask.quantity, sell_order.initial_quantity);
+        }
+        assert_empty_asks(&orderbook);
+        assert_book_has_order(&orderbook, &buy_order.id, &buy_order.side, &88, &price);
+    }
+
+    #[test]
+    fn resting_minimum_checked_against_aggressor_remainder() {
+        let mut orderbook = Orderbook::default();
+        let price = 1;
+
+        let first_sell_order = Order::new(OrderType::Normal, OrderSide::Sell, price, 8, 0);
+        let unmet_sell_order = Order::new(OrderType::Normal, OrderSide::Sell, price, 5, 5);
+        let last_sell_order = Order::new(OrderType::Normal, OrderSide::Sell, price, 2, 2);
+        orderbook.match_order(first_sell_order).unwrap();
+        orderbook.match_order(unmet_sell_order).unwrap();
+        orderbook.match_order(last_sell_order).unwrap();
+        let buy_order = Order::new(OrderType::Normal, OrderSide::Buy, price, 10, 0);
+
+        let trades = orderbook.match_order(buy_order).unwrap();
+
+        // Only 2 is left for the second order after the first fills, below its minimum
+        assert_eq!(trades.len(), 2);
+        assert_eq!(trades[0].ask.order_id, first_sell_order.id);
+        assert_eq!(trades[0].ask.quantity, 8);
+        assert_eq!(trades[1].ask.order_id, last_sell_order.id);
+        assert_eq!(trades[1].ask.quantity, 2);
+        assert_book_has_order(
+            &orderbook,
+            &unmet_sell_order.id,
+            &unmet_sell_order.side,
+            &5,
+            &price,
+        );
+        assert!(!orderbook.orders.contains_key(&buy_order.id));
+    }
+
     #[test]
     fn can_cancel_order() {
         let mut orderbook = Orderbook::new(None);
@@ -681,11 +2291,11 @@ mod tests {
         let order = Order::new(OrderType::Normal, OrderSide::Buy, 1, 1, 0);
         let trades = orderbook.match_order(order).unwrap();
         let cancellation = orderbook
-            .cancel_order(CancelRequestType::External, order.id)
+            .cancel_order(CancelRequestType::User, order.id)
             .unwrap();
 
         assert!(trades.is_empty());
-        assert_eq!(cancellation.order, order);
+        assert_eq!(cancellation.order, rested(order, 1));
         assert_empty_book(&orderbook)
     }
 
@@ -705,13 +2315,17 @@ mod tests {
             remaining_quantity: 1,
             minimum_quantity: 1,
             virtual_remaining_quantity: 1,
+            inserted_at: Utc::now().timestamp_millis(),
+            sequence: 0,
+            min_fill_ratio: None,
+            account_id: None,
         };
 
         let (cancelled_order, second_trades) = orderbook.modify_order(modified_order).unwrap();
 
         assert!(first_trades.is_empty());
         assert!(second_trades.is_empty());
-        assert_eq!(order, cancelled_order.order);
+        assert_eq!(rested(order, 1), cancelled_order.order);
         assert_book_has_order(&orderbook, &modified_order.id, &modified_order.side, &1, &2)
     }
 
@@ -733,12 +2347,16 @@ mod tests {
             remaining_quantity: 1,
             minimum_quantity: 1,
             virtual_remaining_quantity: 1,
+            inserted_at: Utc::now().timestamp_millis(),
+            sequence: 0,
+            min_fill_ratio: None,
+            account_id: None,
         };
         let (cancelled_order, third_trades) = orderbook.modify_order(modified_order).unwrap();
 
         assert!(first_trades.is_empty());
         assert!(second_trades.is_empty());
-        assert_eq!(sell_order, cancelled_order.order);
+        assert_eq!(rested(sell_order, 2), cancelled_order.order);
         assert_trade(
             &third_trades,
             0,
@@ -755,4 +2373,2313 @@ mod tests {
         );
         assert_empty_book(&orderbook)
     }
+
+    #[test]
+    fn can_cancel_level() {
+        let mut orderbook = Orderbook::new(None);
+        let price = 1;
+
+        let buy_order_1 = Order::new(OrderType::Normal, OrderSide::Buy, price, 1, 0);
+        let buy_order_2 = Order::new(OrderType::Normal, OrderSide::Buy, price, 2, 0);
+        let buy_order_3 = Order::new(OrderType::Normal, OrderSide::Buy, price, 3, 0);
+        let other_level_order = Order::new(OrderType::Normal, OrderSide::Buy, 2, 1, 0);
+
+        orderbook.match_order(buy_order_1).unwrap();
+        orderbook.match_order(buy_order_2).unwrap();
+        orderbook.match_order(buy_order_3).unwrap();
+        orderbook.match_order(other_level_order).unwrap();
+
+        let cancelled_orders = orderbook.cancel_level(OrderSide::Buy, price, None);
+
+        assert_eq!(cancelled_orders.len(), 3);
+        assert_eq!(cancelled_orders[0].order, rested(buy_order_1, 1));
+        assert_eq!(cancelled_orders[1].order, rested(buy_order_2, 2));
+        assert_eq!(cancelled_orders[2].order, rested(buy_order_3, 3));
+        assert!(orderbook.bid_levels.get_orders(&price).is_none());
+        assert_book_has_order(
+            &orderbook,
+            &other_level_order.id,
+            &other_level_order.side,
+            &1,
+            &2,
+        );
+    }
+
+    #[test]
+    fn trades_tagged_with_aggressor_side() {
+        let mut orderbook = Orderbook::new(None);
+        let price = 1;
+
+        let sell_order_1 = Order::new(OrderType::Normal, OrderSide::Sell, price, 1, 0);
+        let sell_order_2 = Order::new(OrderType::Normal, OrderSide::Sell, price, 1, 0);
+        let buy_order = Order::new(OrderType::Normal, OrderSide::Buy, price, 2, 0);
+
+        orderbook.match_order(sell_order_1).unwrap();
+        orderbook.match_order(sell_order_2).unwrap();
+        let trades = orderbook.match_order(buy_order).unwrap();
+
+        assert_eq!(trades.len(), 2);
+        assert!(trades
+            .iter()
+            .all(|trade| trade.aggressor_side == OrderSide::Buy));
+    }
+
+    #[test]
+    fn recent_trades_returned_newest_first() {
+        let mut orderbook = Orderbook::new(None);
+        let price = 1;
+
+        let sell_order = Order::new(OrderType::Normal, OrderSide::Sell, price, 3, 0);
+        orderbook.match_order(sell_order).unwrap();
+
+        let mut trades = vec![];
+        for _ in 0..3 {
+            let buy_order = Order::new(OrderType::Normal, OrderSide::Buy, price, 1, 0);
+            trades.extend(orderbook.match_order(buy_order).unwrap());
+        }
+
+        let (reply_sender, mut reply_receiver) = oneshot::channel();
+        orderbook
+            .place_trade_request(OrderRequest::RecentTrades(2, reply_sender))
+            .unwrap();
+        let recent_trades = reply_receiver.try_recv().unwrap();
+
+        assert_eq!(recent_trades, vec![trades[2].clone(), trades[1].clone()]);
+    }
+
+    #[test]
+    fn trades_between_returns_only_matching_pairs() {
+        let mut orderbook = Orderbook::new(None);
+        let price = 1;
+
+        let buy_order = Order::new(OrderType::Normal, OrderSide::Buy, price, 5, 0);
+        let sell_order = Order::new(OrderType::Normal, OrderSide::Sell, price, 1, 0);
+        let other_sell_order = Order::new(OrderType::Normal, OrderSide::Sell, price, 1, 0);
+        orderbook.match_order(buy_order).unwrap();
+
+        let mut trades = vec![];
+        for order in [sell_order, other_sell_order, sell_order] {
+            trades.extend(orderbook.match_order(order).unwrap());
+        }
+
+        let expected_trades = vec![trades[0].clone(), trades[2].clone()];
+        assert_eq!(
+            orderbook.trades_between(buy_order.id, sell_order.id),
+            expected_trades
+        );
+        assert_eq!(
+            orderbook.trades_between(sell_order.id, buy_order.id),
+            expected_trades
+        );
+        assert!(orderbook
+            .trades_between(sell_order.id, other_sell_order.id)
+            .is_empty());
+    }
+
+    #[test]
+    fn restored_snapshot_preserves_matching_priority() {
+        let mut orderbook = Orderbook::new(None);
+        let price = 1;
+
+        let sell_orders: Vec<Order> = (1..=3)
+            .map(|quantity| Order::new(OrderType::Normal, OrderSide::Sell, price, quantity, 0))
+            .collect();
+        for sell_order in &sell_orders {
+            orderbook.match_order(*sell_order).unwrap();
+        }
+
+        let mut snapshot = orderbook.snapshot();
+        snapshot.reverse();
+        let mut restored_orderbook =
+            Orderbook::restore(None, OrderbookConfig::default(), snapshot).unwrap();
+
+        let buy_order = Order::new(OrderType::Kill, OrderSide::Buy, price, 6, 0);
+        let trades = restored_orderbook.match_order(buy_order).unwrap();
+        let matched_ids: Vec<Uuid> = trades.iter().map(|trade| trade.ask.order_id).collect();
+        let sell_order_ids: Vec<Uuid> = sell_orders.iter().map(|order| order.id).collect();
+        assert_eq!(matched_ids, sell_order_ids);
+
+        let later_order = Order::new(OrderType::Normal, OrderSide::Sell, price, 1, 0);
+        restored_orderbook.match_order(later_order).unwrap();
+        assert_eq!(restored_orderbook.orders[&later_order.id].sequence, 4);
+    }
+
+    #[test]
+    fn book_snapshot_diff_shows_single_added_order() {
+        let mut orderbook = Orderbook::default();
+        let resting_order = Order::new(OrderType::Normal, OrderSide::Sell, 10, 2, 0);
+        orderbook.match_order(resting_order).unwrap();
+        let before = orderbook.book_snapshot();
+
+        let new_order = Order::new(OrderType::Normal, OrderSide::Buy, 5, 1, 0);
+        orderbook.match_order(new_order).unwrap();
+        let after = orderbook.book_snapshot();
+
+        assert_eq!(
+            before.diff(&after),
+            vec![BookDiff::Added(rested(new_order, 2))]
+        );
+        assert!(after.diff(&after).is_empty());
+
+        let crossing_order = Order::new(OrderType::Normal, OrderSide::Buy, 10, 1, 0);
+        orderbook.match_order(crossing_order).unwrap();
+
+        assert_eq!(
+            after.diff(&orderbook.book_snapshot()),
+            vec![BookDiff::Changed {
+                before: rested(resting_order, 1),
+                after: Order {
+                    remaining_quantity: 1,
+                    virtual_remaining_quantity: 1,
+                    ..rested(resting_order, 1)
+                },
+            }]
+        );
+    }
+
+    #[test]
+    fn full_book_json_contains_each_order() {
+        let mut orderbook = Orderbook::new(None);
+
+        let orders = [
+            Order::new(OrderType::Normal, OrderSide::Buy, 1, 1, 0),
+            Order::new(OrderType::Normal, OrderSide::Buy, 1, 2, 1),
+            Order::new(OrderType::Normal, OrderSide::Sell, 2, 3, 0),
+        ];
+        for order in orders {
+            orderbook.match_order(order).unwrap();
+        }
+
+        let (reply_sender, mut reply_receiver) = oneshot::channel();
+        orderbook
+            .place_trade_request(OrderRequest::Book(reply_sender))
+            .unwrap();
+        let book_json = serde_json::to_value(reply_receiver.try_recv().unwrap()).unwrap();
+
+        let book_orders = book_json.as_array().unwrap();
+        assert_eq!(book_orders.len(), orders.len());
+        for (order, book_order) in orders.iter().zip(book_orders) {
+            assert_eq!(book_order["id"].as_str().unwrap(), order.id.to_string());
+            assert_eq!(
+                book_order["remaining_quantity"].as_u64().unwrap(),
+                order.remaining_quantity
+            );
+        }
+    }
+
+    #[test]
+    fn resubmitted_crossing_order_with_same_id_rejected() {
+        let mut orderbook = Orderbook::new(None);
+        let price = 1;
+
+        let buy_order = Order::new(OrderType::Normal, OrderSide::Buy, price, 1, 0);
+        let resubmitted_order = Order {
+            side: OrderSide::Sell,
+            ..buy_order
+        };
+
+        orderbook.match_order(buy_order).unwrap();
+
+        assert!(orderbook.match_order(resubmitted_order).is_err());
+        assert_book_has_order(&orderbook, &buy_order.id, &buy_order.side, &1, &price);
+        assert_empty_asks(&orderbook);
+    }
+
+    #[test]
+    fn can_modify_minimum_quantity() {
+        let mut orderbook = Orderbook::new(None);
+        let price = 1;
+
+        let buy_order = Order::new(OrderType::Normal, OrderSide::Buy, price, 2, 0);
+        orderbook.match_order(buy_order).unwrap();
+
+        let modified_order = Order {
+            minimum_quantity: 2,
+            ..buy_order
+        };
+        orderbook.modify_order(modified_order).unwrap();
+
+        let sell_order = Order::new(OrderType::Normal, OrderSide::Sell, price, 1, 0);
+        let trades = orderbook.match_order(sell_order).unwrap();
+
+        assert!(trades.is_empty());
+        assert_eq!(
+            orderbook
+                .orders
+                .get(&buy_order.id)
+                .unwrap()
+                .minimum_quantity,
+            2
+        );
+        assert_book_has_order(&orderbook, &buy_order.id, &buy_order.side, &2, &price);
+        assert_book_has_order(&orderbook, &sell_order.id, &sell_order.side, &1, &price);
+    }
+
+    #[test]
+    fn cannot_modify_minimum_quantity_above_remaining() {
+        let mut orderbook = Orderbook::new(None);
+        let price = 1;
+
+        let buy_order = Order::new(OrderType::Normal, OrderSide::Buy, price, 2, 0);
+        let sell_order = Order::new(OrderType::Normal, OrderSide::Sell, price, 1, 0);
+        orderbook.match_order(buy_order).unwrap();
+        orderbook.match_order(sell_order).unwrap();
+
+        let modified_order = Order {
+            minimum_quantity: 2,
+            ..buy_order
+        };
+
+        assert!(orderbook.modify_order(modified_order).is_err());
+        assert_book_has_order(&orderbook, &buy_order.id, &buy_order.side, &1, &price);
+    }
+
+    #[test]
+    fn multi_fill_order_published_as_single_batch() {
+        let (market_data_sender, market_data_receiver) = channel::unbounded();
+        let config = OrderbookConfig {
+            batch_market_data_updates: true,
+            ..Default::default()
+        };
+        let mut orderbook = Orderbook::with_config(Some(market_data_sender), config);
+        let price = 1;
+
+        let sell_order_1 = Order::new(OrderType::Normal, OrderSide::Sell, price, 1, 0);
+        let sell_order_2 = Order::new(OrderType::Normal, OrderSide::Sell, price, 1, 0);
+        orderbook.match_order(sell_order_1).unwrap();
+        orderbook.match_order(sell_order_2).unwrap();
+
+        let buy_request = TradeRequest::normal(OrderSide::Buy, price, 2);
+        let market_updates = orderbook
+            .place_trade_request(OrderRequest::Trade(buy_request))
+            .unwrap();
+
+        assert_eq!(market_updates.len(), 2);
+        match market_data_receiver.try_recv().unwrap() {
+            MarketDataUpdate::Batch(updates) => {
+                assert_eq!(updates.len(), 2);
+                assert!(updates
+                    .iter()
+                    .all(|update| matches!(update, MarketDataUpdate::Trade(_))));
+            }
+            _ => panic!("Expected a batch update"),
+        }
+        assert!(market_data_receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn discarded_match_restores_virtual_quantity() {
+        let mut orderbook = Orderbook::new(None);
+
+        let first_ask = Order::new(OrderType::Normal, OrderSide::Sell, 1, 2, 0);
+        let second_ask = Order::new(OrderType::Normal, OrderSide::Sell, 2, 3, 0);
+        orderbook.match_order(first_ask).unwrap();
+        orderbook.match_order(second_ask).unwrap();
+
+        let mut discarded_buy = Order::new(OrderType::Kill, OrderSide::Buy, 2, 10, 10);
+        assert!(orderbook
+            .internal_match_order(&mut discarded_buy)
+            .is_empty());
+        assert_eq!(
+            discarded_buy.virtual_remaining_quantity,
+            discarded_buy.remaining_quantity
+        );
+        for order in orderbook.orders.values() {
+            assert_eq!(order.virtual_remaining_quantity, order.remaining_quantity);
+        }
+
+        let buy_order = Order::new(OrderType::Kill, OrderSide::Buy, 2, 5, 0);
+        let trades = orderbook.match_order(buy_order).unwrap();
+
+        assert_eq!(trades.len(), 2);
+        for (index, (ask, quantity)) in [(first_ask, 2), (second_ask, 3)].into_iter().enumerate() {
+            assert_trade(
+                &trades,
+                index,
+                TradeInfo {
+                    order_id: buy_order.id,
+                    price: buy_order.price,
+                    quantity,
+                },
+                TradeInfo {
+                    order_id: ask.id,
+                    price: ask.price,
+                    quantity,
+                },
+            );
+        }
+        assert_empty_book(&orderbook);
+    }
+
+    fn fast_matches_within(upper_bound: f64) -> u64 {
+        MATCHING_DURATION
+            .metric()
+            .get_histogram()
+            .get_bucket()
+            .iter()
+            .find(|bucket| bucket.get_upper_bound() == upper_bound)
+            .unwrap()
+            .get_cumulative_count()
+    }
+
+    #[test]
+    fn fast_match_observed_in_sub_millisecond_bucket() {
+        let mut orderbook = Orderbook::new(None);
+
+        let buy_order = Order::new(OrderType::Normal, OrderSide::Buy, 1, 1, 0);
+        let sell_order = Order::new(OrderType::Normal, OrderSide::Sell, 1, 1, 0);
+        orderbook.match_order(buy_order).unwrap();
+
+        let fast_matches = fast_matches_within(0.001);
+        orderbook.match_order(sell_order).unwrap();
+
+        assert!(fast_matches_within(0.001) > fast_matches);
+    }
+
+    #[test]
+    fn discarded_match_counted_as_empty_pass() {
+        let mut orderbook = Orderbook::new(None);
+        let price = 1;
+
+        let buy_order = Order::new(OrderType::Normal, OrderSide::Buy, price, 1, 0);
+        let sell_order = Order::new(OrderType::Normal, OrderSide::Sell, price, 2, 2);
+
+        let _guard = METRICS_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        reset_metrics();
+        orderbook.match_order(buy_order).unwrap();
+        let trades = orderbook.match_order(sell_order).unwrap();
+
+        assert!(trades.is_empty());
+        assert!(EMPTY_MATCH_PASSES.get() > 0);
+    }
+
+    /// Buy of quantity 1 with a given id
+    fn buy_request(id: Uuid, price: Price) -> TradeRequest {
+        TradeRequest {
+            id,
+            ..TradeRequest::normal(OrderSide::Buy, price, 1)
+        }
+    }
+
+    fn upsert_request(id: Uuid, price: Price) -> OrderRequest {
+        OrderRequest::Upsert(buy_request(id, price))
+    }
+
+    #[test]
+    fn virtual_quantity_settled_after_discarded_then_partial_modify() {
+        let mut orderbook = Orderbook::default();
+        let near_ask = Order::new(OrderType::Normal, OrderSide::Sell, 10, 2, 0);
+        let far_ask = Order::new(OrderType::Normal, OrderSide::Sell, 11, 3, 0);
+        let bid = Order::new(OrderType::Normal, OrderSide::Buy, 9, 1, 0);
+        for order in [near_ask, far_ask, bid] {
+            orderbook.match_order(order).unwrap();
+        }
+
+        let modify_request = |price, quantity, minimum_quantity| {
+            OrderRequest::Modify(TradeRequest {
+                id: bid.id,
+                minimum_quantity,
+                ..TradeRequest::normal(OrderSide::Buy, price, quantity)
+            })
+        };
+
+        // Sweeps both asks tentatively, but can't reach the minimum so discards
+        let discarded_updates = orderbook
+            .place_trade_request(modify_request(11, 10, 10))
+            .unwrap();
+        assert!(!discarded_updates
+            .iter()
+            .any(|update| matches!(update, MarketDataUpdate::Trade(_))));
+
+        let partial_updates = orderbook
+            .place_trade_request(modify_request(10, 4, 0))
+            .unwrap();
+        assert!(partial_updates
+            .iter()
+            .any(|update| matches!(update, MarketDataUpdate::Trade(_))));
+
+        orderbook.assert_virtual_equals_remaining();
+        assert!(!orderbook.orders.contains_key(&near_ask.id));
+        assert_book_has_order(&orderbook, &far_ask.id, &OrderSide::Sell, &3, &11);
+        assert_book_has_order(&orderbook, &bid.id, &OrderSide::Buy, &2, &10);
+    }
+
+    #[test]
+    fn diverged_virtual_quantity_caught_before_request_applied() {
+        let mut orderbook = Orderbook::default();
+        let sell_order = Order::new(OrderType::Normal, OrderSide::Sell, 10, 2, 0);
+        orderbook.match_order(sell_order).unwrap();
+        orderbook
+            .orders
+            .get_mut(&sell_order.id)
+            .unwrap()
+            .virtual_remaining_quantity = 1;
+
+        let buy_request = TradeRequest::normal(OrderSide::Buy, 10, 1);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            orderbook.place_trade_request(OrderRequest::Trade(buy_request.clone()))
+        }));
+
+        assert!(result.is_err());
+        assert!(orderbook.recent_trades(1).is_empty());
+        assert_book_has_order(&orderbook, &sell_order.id, &OrderSide::Sell, &2, &10);
+
+        orderbook.discard_uncommitted();
+        let updates = orderbook
+            .place_trade_request(OrderRequest::Trade(buy_request))
+            .unwrap();
+        assert!(updates
+            .iter()
+            .any(|update| matches!(update, MarketDataUpdate::Trade(_))));
+    }
+
+    fn deduplicating_orderbook() -> Orderbook {
+        let config = OrderbookConfig {
+            duplicate_grace_millis: Some(1_000),
+            ..Default::default()
+        };
+        let mut orderbook = Orderbook::with_config(None, config);
+        orderbook.set_time(10_000);
+        orderbook
+    }
+
+    #[test]
+    fn identical_resubmission_within_grace_window_ignored() {
+        let mut orderbook = deduplicating_orderbook();
+        let order_id = Uuid::new_v4();
+
+        orderbook
+            .place_trade_request(OrderRequest::Trade(buy_request(order_id, 1)))
+            .unwrap();
+        orderbook.set_time(10_500);
+        let retry_updates = orderbook
+            .place_trade_request(OrderRequest::Trade(buy_request(order_id, 1)))
+            .unwrap();
+
+        assert!(retry_updates.is_empty());
+        assert!(orderbook.recent_rejections(1).is_empty());
+        assert_book_has_order(&orderbook, &order_id, &OrderSide::Buy, &1, &1);
+        assert_eq!(orderbook.orders.len(), 1);
+
+        orderbook.set_time(12_000);
+        let late_retry_updates = orderbook
+            .place_trade_request(OrderRequest::Trade(buy_request(order_id, 1)))
+            .unwrap();
+        assert!(matches!(
+            late_retry_updates.as_slice(),
+            [MarketDataUpdate::OrderRejected { .. }]
+        ));
+    }
+
+    #[test]
+    fn conflicting_resubmission_within_grace_window_rejected() {
+        let mut orderbook = deduplicating_orderbook();
+        let order_id = Uuid::new_v4();
+
+        orderbook
+            .place_trade_request(OrderRequest::Trade(buy_request(order_id, 1)))
+            .unwrap();
+        let conflicting_updates = orderbook
+            .place_trade_request(OrderRequest::Trade(buy_request(order_id, 2)))
+            .unwrap();
+
+        assert_eq!(
+            conflicting_updates,
+            vec![MarketDataUpdate::OrderRejected {
+                id: order_id,
+                reason: "Order id already in use".to_string(),
+            }]
+        );
+        assert_book_has_order(&orderbook, &order_id, &OrderSide::Buy, &1, &1);
+    }
+
+    #[test]
+    fn zero_quantity_order_rejected() {
+        let mut orderbook = Orderbook::new(None);
+        let order_id = Uuid::new_v4();
+        let trade_request = TradeRequest {
+            id: order_id,
+            ..TradeRequest::normal(OrderSide::Buy, 1, 0)
+        };
+
+        let market_updates = orderbook
+            .place_trade_request(OrderRequest::Trade(trade_request))
+            .unwrap();
+
+        assert_eq!(
+            market_updates,
+            vec![MarketDataUpdate::OrderRejected {
+                id: order_id,
+                reason: "Quantity cannot be zero".to_string(),
+            }]
+        );
+        assert_empty_book(&orderbook);
+    }
+
+    #[test]
+    fn quantity_underflow_errors_instead_of_panicking() {
+        assert!(matches!(
+            checked_quantity_sub(1, 2),
+            Err(ProcessTradeError::QuantityUnderflow)
+        ));
+
+        // Remaining above initial can only come from a malformed order
+        let malformed_order = Order {
+            remaining_quantity: 3,
+            virtual_remaining_quantity: 3,
+            ..Order::new(OrderType::Normal, OrderSide::Buy, 1, 1, 0)
+        };
+        let mut orderbook = Orderbook::restore(
+            None,
+            OrderbookConfig::default(),
+            vec![rested(malformed_order, 1)],
+        )
+        .unwrap();
+
+        let error = orderbook.modify_order(malformed_order).unwrap_err();
+        assert_eq!(error.to_string(), "Quantity underflow");
+        assert!(orderbook.orders.contains_key(&malformed_order.id));
+
+        let sell_order = Order::new(OrderType::Normal, OrderSide::Sell, 2, 1, 0);
+        orderbook.match_order(sell_order).unwrap();
+        let mut malformed_buy_order = Order {
+            id: Uuid::new_v4(),
+            price: 2,
+            ..malformed_order
+        };
+        assert!(orderbook
+            .internal_match_order(&mut malformed_buy_order)
+            .is_empty());
+        assert_book_has_order(&orderbook, &sell_order.id, &OrderSide::Sell, &1, &2);
+    }
+
+    #[test]
+    fn invalid_minimum_quantity_publishes_rejection() {
+        let (tx, rx) = channel::unbounded();
+        let mut orderbook = Orderbook::new(Some(tx));
+        let order_id = Uuid::new_v4();
+        let trade_request = TradeRequest {
+            minimum_quantity: 2,
+            ..buy_request(order_id, 1)
+        };
+
+        orderbook
+            .place_trade_request(OrderRequest::Trade(trade_request))
+            .unwrap();
+
+        let expected_rejection = MarketDataUpdate::OrderRejected {
+            id: order_id,
+            reason: "Minimum quantity > quantity".to_string(),
+        };
+        assert_eq!(rx.try_recv().unwrap(), expected_rejection);
+        assert_empty_book(&orderbook);
+    }
+
+    fn safe_reprice_request(order: &Order, price: Price) -> OrderRequest {
+        OrderRequest::SafeReprice(TradeRequest {
+            id: order.id,
+            order_type: order.type_,
+            minimum_quantity: order.minimum_quantity,
+            ..TradeRequest::normal(order.side, price, order.initial_quantity)
+        })
+    }
+
+    #[test]
+    fn safe_reprice_to_non_crossing_price_modifies_order() {
+        let mut orderbook = Orderbook::default();
+        let buy_order = Order::new(OrderType::Normal, OrderSide::Buy, 5, 1, 0);
+        let sell_order = Order::new(OrderType::Normal, OrderSide::Sell, 10, 1, 0);
+        orderbook.match_order(buy_order).unwrap();
+        orderbook.match_order(sell_order).unwrap();
+
+        let market_updates = orderbook
+            .place_trade_request(safe_reprice_request(&buy_order, 9))
+            .unwrap();
+
+        assert!(matches!(
+            market_updates.as_slice(),
+            [MarketDataUpdate::Cancellation(_)]
+        ));
+        assert_book_has_order(&orderbook, &buy_order.id, &OrderSide::Buy, &1, &9);
+        assert_book_has_order(&orderbook, &sell_order.id, &OrderSide::Sell, &1, &10);
+    }
+
+    #[test]
+    fn safe_reprice_to_crossing_price_rejected() {
+        let mut orderbook = Orderbook::default();
+        let buy_order = Order::new(OrderType::Normal, OrderSide::Buy, 5, 1, 0);
+        let sell_order = Order::new(OrderType::Normal, OrderSide::Sell, 10, 1, 0);
+        orderbook.match_order(buy_order).unwrap();
+        orderbook.match_order(sell_order).unwrap();
+
+        let market_updates = orderbook
+            .place_trade_request(safe_reprice_request(&buy_order, 10))
+            .unwrap();
+
+        assert_eq!(
+            market_updates,
+            vec![MarketDataUpdate::OrderRejected {
+                id: buy_order.id,
+                reason: "Safe reprice would cross".to_string(),
+            }]
+        );
+        assert_eq!(orderbook.orders[&buy_order.id], rested(buy_order, 1));
+        assert_book_has_order(&orderbook, &sell_order.id, &OrderSide::Sell, &1, &10);
+    }
+
+    #[test]
+    fn modify_rejected_by_same_validation_as_new_order() {
+        let mut orderbook = Orderbook::new(None);
+        let order_id = Uuid::new_v4();
+        let resting_request = buy_request(order_id, 1);
+        orderbook
+            .place_trade_request(OrderRequest::Trade(resting_request.clone()))
+            .unwrap();
+
+        let invalid_request = TradeRequest {
+            minimum_quantity: 2,
+            ..resting_request
+        };
+        let new_order_rejection = Order::try_from(invalid_request.clone()).unwrap_err();
+        let market_updates = orderbook
+            .place_trade_request(OrderRequest::Modify(invalid_request))
+            .unwrap();
+
+        assert_eq!(
+            market_updates,
+            vec![MarketDataUpdate::OrderRejected {
+                id: order_id,
+                reason: new_order_rejection.to_string(),
+            }]
+        );
+        assert_eq!(orderbook.orders[&order_id].minimum_quantity, 0);
+    }
+
+    #[test]
+    fn create_partial_fill_cancel_emits_lifecycle_events() {
+        let mut orderbook = Orderbook::new(None);
+        let lifecycle_events = orderbook.subscribe_lifecycle_events();
+
+        let sell_order = Order::new(OrderType::Normal, OrderSide::Sell, 10, 10, 0);
+        let buy_order = Order::new(OrderType::Normal, OrderSide::Buy, 10, 4, 0);
+        orderbook.trade_market_updates(sell_order);
+        orderbook.trade_market_updates(buy_order);
+        orderbook
+            .place_trade_request(OrderRequest::Cancel(CancelRequestType::User, sell_order.id))
+            .unwrap();
+
+        let partially_filled_sell_order = Order {
+            remaining_quantity: 6,
+            virtual_remaining_quantity: 6,
+            ..rested(sell_order, 1)
+        };
+        assert_eq!(
+            lifecycle_events.try_iter().collect::<Vec<_>>(),
+            vec![
+                LifecycleEvent::Accepted {
+                    order_id: sell_order.id
+                },
+                LifecycleEvent::Rested(rested(sell_order, 1)),
+                LifecycleEvent::Accepted {
+                    order_id: buy_order.id
+                },
+                LifecycleEvent::PartiallyFilled {
+                    order_id: sell_order.id,
+                    filled_quantity: 4,
+                    remaining_quantity: 6,
+                },
+                LifecycleEvent::Filled {
+                    order_id: buy_order.id,
+                    filled_quantity: 4,
+                },
+                LifecycleEvent::Cancelled(CancelledOrder {
+                    cancel_request_type: CancelRequestType::User,
+                    order: partially_filled_sell_order,
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn orders_at_same_price_fill_in_time_priority() {
+        let mut orderbook = Orderbook::default();
+        let first_buy = Order::new(OrderType::Normal, OrderSide::Buy, 10, 2, 0);
+        let second_buy = Order::new(OrderType::Normal, OrderSide::Buy, 10, 2, 0);
+        let third_buy = Order::new(OrderType::Normal, OrderSide::Buy, 10, 2, 0);
+        for order in [first_buy, second_buy, third_buy] {
+            orderbook.match_order(order).unwrap();
+        }
+        for (position, order) in [first_buy, second_buy, third_buy].iter().enumerate() {
+            assert_eq!(
+                orderbook.bid_levels.order_position(&10, &order.id),
+                Some(position)
+            );
+        }
+
+        let sell_order = Order::new(OrderType::Normal, OrderSide::Sell, 10, 3, 0);
+        let trades = orderbook.match_order(sell_order).unwrap();
+
+        let filled: Vec<(Uuid, Quantity)> = trades
+            .iter()
+            .map(|trade| (trade.bid.order_id, trade.bid.quantity))
+            .collect();
+        assert_eq!(filled, vec![(first_buy.id, 2), (second_buy.id, 1)]);
+        assert_eq!(
+            orderbook.bid_levels.order_position(&10, &first_buy.id),
+            None
+        );
+        assert_eq!(
+            orderbook.bid_levels.order_position(&10, &second_buy.id),
+            Some(0)
+        );
+        assert_eq!(
+            orderbook.bid_levels.order_position(&10, &third_buy.id),
+            Some(1)
+        );
+        assert_book_has_order(&orderbook, &second_buy.id, &OrderSide::Buy, &1, &10);
+        assert_book_has_order(&orderbook, &third_buy.id, &OrderSide::Buy, &2, &10);
+    }
+
+    #[test]
+    fn cancelling_front_order_emits_queue_update_for_orders_behind() {
+        let mut orderbook = Orderbook::new(None);
+        let front_order = Order::new(OrderType::Normal, OrderSide::Buy, 10, 2, 0);
+        let second_order = Order::new(OrderType::Normal, OrderSide::Buy, 10, 3, 0);
+        let third_order = Order::new(OrderType::Normal, OrderSide::Buy, 10, 1, 0);
+        for order in [front_order, second_order, third_order] {
+            orderbook.match_order(order).unwrap();
+        }
+        let lifecycle_events = orderbook.subscribe_lifecycle_events();
+
+        orderbook.cancel_order(CancelRequestType::User, front_order.id);
+
+        let queue_updates: Vec<LifecycleEvent> = lifecycle_events
+            .try_iter()
+            .filter(|event| matches!(event, LifecycleEvent::QueueUpdate { .. }))
+            .collect();
+        assert_eq!(
+            queue_updates,
+            vec![
+                LifecycleEvent::QueueUpdate {
+                    order_id: second_order.id,
+                    volume_ahead: 0,
+                },
+                LifecycleEvent::QueueUpdate {
+                    order_id: third_order.id,
+                    volume_ahead: 3,
+                },
+            ]
+        );
+
+        let sell_order = Order::new(OrderType::Normal, OrderSide::Sell, 10, 1, 0);
+        orderbook.match_order(sell_order).unwrap();
+
+        let queue_updates: Vec<LifecycleEvent> = lifecycle_events
+            .try_iter()
+            .filter(|event| matches!(event, LifecycleEvent::QueueUpdate { .. }))
+            .collect();
+        assert_eq!(
+            queue_updates,
+            vec![LifecycleEvent::QueueUpdate {
+                order_id: third_order.id,
+                volume_ahead: 2,
+            }]
+        );
+    }
+
+    #[test]
+    fn filling_oco_leg_cancels_sibling() {
+        let mut orderbook = Orderbook::new(None);
+        let take_profit = TradeRequest::normal(OrderSide::Sell, 12, 1);
+        let stop_loss = TradeRequest::normal(OrderSide::Buy, 8, 1);
+        let stop_loss_id = stop_loss.id;
+
+        orderbook
+            .place_trade_request(OrderRequest::Oco(take_profit, stop_loss))
+            .unwrap();
+        assert_eq!(orderbook.orders.len(), 2);
+
+        let buy_order = Order::new(OrderType::Kill, OrderSide::Buy, 12, 1, 0);
+        let updates = orderbook.trade_market_updates(buy_order);
+
+        assert_eq!(updates.len(), 2);
+        assert!(matches!(updates[0], MarketDataUpdate::Trade(_)));
+        assert!(matches!(
+            &updates[1],
+            MarketDataUpdate::Cancellation(cancelled_order)
+                if cancelled_order.order.id == stop_loss_id
+                    && cancelled_order.cancel_request_type == CancelRequestType::OcoSiblingFilled
+        ));
+        assert_empty_book(&orderbook);
+        assert!(orderbook.oco_links.is_empty());
+    }
+
+    #[test]
+    fn oco_second_leg_rejected_when_first_fills_on_entry() {
+        let mut orderbook = Orderbook::new(None);
+        let sell_order = Order::new(OrderType::Normal, OrderSide::Sell, 10, 1, 0);
+        orderbook.match_order(sell_order).unwrap();
+
+        let crossing_leg = TradeRequest::normal(OrderSide::Buy, 10, 1);
+        let resting_leg = TradeRequest::normal(OrderSide::Buy, 5, 1);
+        let resting_leg_id = resting_leg.id;
+        let updates = orderbook
+            .place_trade_request(OrderRequest::Oco(crossing_leg, resting_leg))
+            .unwrap();
+
+        assert!(matches!(updates[0], MarketDataUpdate::Trade(_)));
+        assert_eq!(
+            updates[1],
+            MarketDataUpdate::OrderRejected {
+                id: resting_leg_id,
+                reason: "OCO sibling filled".to_string(),
+            }
+        );
+        assert_empty_book(&orderbook);
+    }
+
+    #[test]
+    fn recent_rejections_list_reasons_newest_first() {
+        let mut orderbook = Orderbook::new(None);
+        orderbook.set_time(1_000);
+
+        let invalid_minimum_quantity_request = TradeRequest {
+            minimum_quantity: 2,
+            ..TradeRequest::normal(OrderSide::Buy, 1, 1)
+        };
+        let resting_request = TradeRequest::normal(OrderSide::Buy, 1, 1);
+        let duplicate_id_request = TradeRequest {
+            price: 2,
+            ..resting_request.clone()
+        };
+        let invalid_order_id = invalid_minimum_quantity_request.id;
+        let duplicate_order_id = duplicate_id_request.id;
+
+        for trade_request in [
+            invalid_minimum_quantity_request,
+            resting_request,
+            duplicate_id_request,
+        ] {
+            orderbook
+                .place_trade_request(OrderRequest::Trade(trade_request))
+                .unwrap();
+        }
+
+        let (reply_sender, mut reply_receiver) = oneshot::channel();
+        orderbook
+            .place_trade_request(OrderRequest::RecentRejections(10, reply_sender))
+            .unwrap();
+
+        assert_eq!(
+            reply_receiver.try_recv().unwrap(),
+            vec![
+                RejectedOrder {
+                    order_id: duplicate_order_id,
+                    reason: "Order id already in use".to_string(),
+                    rejected_at: 1_000,
+                },
+                RejectedOrder {
+                    order_id: invalid_order_id,
+                    reason: "Minimum quantity > quantity".to_string(),
+                    rejected_at: 1_000,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn upsert_creates_new_order() {
+        let mut orderbook = Orderbook::new(None);
+        let order_id = Uuid::new_v4();
+
+        let market_updates = orderbook
+            .place_trade_request(upsert_request(order_id, 1))
+            .unwrap();
+
+        assert!(market_updates.is_empty());
+        assert_book_has_order(&orderbook, &order_id, &OrderSide::Buy, &1, &1);
+    }
+
+    #[test]
+    fn upsert_modifies_existing_order() {
+        let mut orderbook = Orderbook::new(None);
+        let order_id = Uuid::new_v4();
+
+        orderbook
+            .place_trade_request(upsert_request(order_id, 1))
+            .unwrap();
+        let market_updates = orderbook
+            .place_trade_request(upsert_request(order_id, 2))
+            .unwrap();
+
+        assert_eq!(market_updates.len(), 1);
+        match &market_updates[0] {
+            MarketDataUpdate::Cancellation(cancelled_order) => {
+                assert_eq!(cancelled_order.order.id, order_id);
+                assert_eq!(cancelled_order.order.price, 1);
+            }
+            _ => panic!("Expected a cancellation update"),
+        }
+        assert!(orderbook.bid_levels.get_orders(&1).is_none());
+        assert_book_has_order(&orderbook, &order_id, &OrderSide::Buy, &1, &2);
+    }
+
+    #[test]
+    fn quote_for_two_sided_book() {
+        let mut orderbook = Orderbook::new(None);
+
+        let buy_order = Order::new(OrderType::Normal, OrderSide::Buy, 10, 1, 0);
+        let sell_order = Order::new(OrderType::Normal, OrderSide::Sell, 13, 1, 0);
+        orderbook.match_order(buy_order).unwrap();
+        orderbook.match_order(sell_order).unwrap();
+
+        assert_eq!(
+            orderbook.quote(),
+            Quote {
+                best_bid: Some(10),
+                best_ask: Some(13),
+                mid_price: Some(11.5),
+                spread: Some(3),
+            }
+        );
+    }
+
+    #[test]
+    fn quote_for_one_sided_book() {
+        let mut orderbook = Orderbook::new(None);
+
+        let buy_order = Order::new(OrderType::Normal, OrderSide::Buy, 10, 1, 0);
+        orderbook.match_order(buy_order).unwrap();
+
+        assert_eq!(
+            orderbook.quote(),
+            Quote {
+                best_bid: Some(10),
+                best_ask: None,
+                mid_price: None,
+                spread: None,
+            }
+        );
+    }
+
+    #[test]
+    fn quote_for_empty_book() {
+        let orderbook = Orderbook::new(None);
+
+        assert_eq!(
+            orderbook.quote(),
+            Quote {
+                best_bid: None,
+                best_ask: None,
+                mid_price: None,
+                spread: None,
+            }
+        );
+    }
+
+    #[test]
+    fn can_replace_order_with_new_id() {
+        let mut orderbook = Orderbook::new(None);
+        let price = 1;
+
+        let buy_order = Order::new(OrderType::Normal, OrderSide::Buy, price, 3, 0);
+        let sell_order = Order::new(OrderType::Normal, OrderSide::Sell, price, 1, 0);
+        orderbook.match_order(buy_order).unwrap();
+        orderbook.match_order(sell_order).unwrap();
+
+        let replacement_order = Order::new(OrderType::Normal, OrderSide::Buy, 2, 3, 0);
+        let (cancelled_order, trades) = orderbook
+            .replace_order(buy_order.id, replacement_order)
+            .unwrap();
+
+        assert!(trades.is_empty());
+        assert_eq!(cancelled_order.order.id, buy_order.id);
+        assert!(!orderbook.orders.contains_key(&buy_order.id));
+        assert!(orderbook.bid_levels.get_orders(&price).is_none());
+        assert_book_has_order(
+            &orderbook,
+            &replacement_order.id,
+            &replacement_order.side,
+            &2,
+            &2,
+        );
+    }
+
+    #[test]
+    fn cannot_replace_order_with_id_in_use() {
+        let mut orderbook = Orderbook::new(None);
+
+        let buy_order_1 = Order::new(OrderType::Normal, OrderSide::Buy, 1, 1, 0);
+        let buy_order_2 = Order::new(OrderType::Normal, OrderSide::Buy, 2, 1, 0);
+        orderbook.match_order(buy_order_1).unwrap();
+        orderbook.match_order(buy_order_2).unwrap();
+
+        let replacement_order = Order {
+            price: 3,
+            ..buy_order_2
+        };
+
+        assert!(orderbook
+            .replace_order(buy_order_1.id, replacement_order)
+            .is_err());
+        assert_book_has_order(&orderbook, &buy_order_1.id, &buy_order_1.side, &1, &1);
+        assert_book_has_order(&orderbook, &buy_order_2.id, &buy_order_2.side, &1, &2);
+    }
+
+    fn modify_limited_orderbook() -> Orderbook {
+        let config = OrderbookConfig {
+            max_modify_price_change: Some(5),
+            max_modify_quantity_change: Some(10),
+            ..Default::default()
+        };
+        Orderbook::with_config(None, config)
+    }
+
+    #[test]
+    fn modify_within_limits_accepted() {
+        let mut orderbook = modify_limited_orderbook();
+
+        let order = Order::new(OrderType::Normal, OrderSide::Buy, 10, 10, 0);
+        orderbook.match_order(order).unwrap();
+
+        let modified_order = Order {
+            price: 15,
+            initial_quantity: 20,
+            ..order
+        };
+
+        assert!(orderbook.modify_order(modified_order).is_ok());
+        assert_book_has_order(&orderbook, &order.id, &order.side, &20, &15);
+    }
+
+    #[test]
+    fn modify_over_limits_rejected() {
+        let mut orderbook = modify_limited_orderbook();
+
+        let order = Order::new(OrderType::Normal, OrderSide::Buy, 10, 10, 0);
+        orderbook.match_order(order).unwrap();
+
+        let price_modified_order = Order { price: 16, ..order };
+        let quantity_modified_order = Order {
+            initial_quantity: 21,
+            ..order
+        };
+
+        assert!(orderbook.modify_order(price_modified_order).is_err());
+        assert!(orderbook.modify_order(quantity_modified_order).is_err());
+        assert_book_has_order(&orderbook, &order.id, &order.side, &10, &10);
+    }
+
+    #[test]
+    fn trade_audit_records_queue_position_and_wait_time() {
+        let mut orderbook = Orderbook::default();
+        let price = 1;
+
+        let sell_order_1 = Order::new(OrderType::Normal, OrderSide::Sell, price, 1, 0);
+        let sell_order_2 = Order::new(OrderType::Normal, OrderSide::Sell, price, 1, 0);
+        orderbook.match_order(sell_order_1).unwrap();
+        orderbook.match_order(sell_order_2).unwrap();
+
+        orderbook
+            .orders
+            .get_mut(&sell_order_2.id)
+            .unwrap()
+            .inserted_at -= 60_000;
+
+        let buy_order = Order::new(OrderType::Normal, OrderSide::Buy, price, 2, 0);
+        let trades = orderbook.match_order(buy_order).unwrap();
+
+        let audit = trades.get(1).unwrap().resting_order_audit;
+        assert_eq!(trades.get(1).unwrap().ask.order_id, sell_order_2.id);
+        assert_eq!(audit.queue_position, 1);
+        assert!(audit.resting_duration_ms >= 60_000);
+        assert!(audit.resting_duration_ms < 61_000);
+    }
+
+    #[test]
+    fn fill_estimate_for_full_cross() {
+        let mut orderbook = Orderbook::default();
+
+        let sell_order_1 = Order::new(OrderType::Normal, OrderSide::Sell, 10, 5, 0);
+        let sell_order_2 = Order::new(OrderType::Normal, OrderSide::Sell, 11, 5, 0);
+        orderbook.match_order(sell_order_1).unwrap();
+        orderbook.match_order(sell_order_2).unwrap();
+
+        assert_eq!(orderbook.fill_estimate(OrderSide::Buy, 11, 10), 1.0);
+    }
+
+    #[test]
+    fn fill_estimate_for_partial_cross() {
+        let mut orderbook = Orderbook::default();
+
+        let sell_order_1 = Order::new(OrderType::Normal, OrderSide::Sell, 10, 5, 0);
+        let sell_order_2 = Order::new(OrderType::Normal, OrderSide::Sell, 11, 5, 0);
+        orderbook.match_order(sell_order_1).unwrap();
+        orderbook.match_order(sell_order_2).unwrap();
+
+        assert_eq!(orderbook.fill_estimate(OrderSide::Buy, 10, 10), 0.5);
+    }
+
+    #[test]
+    fn fill_estimate_for_no_cross() {
+        let mut orderbook = Orderbook::default();
+
+        let buy_order = Order::new(OrderType::Normal, OrderSide::Buy, 10, 5, 0);
+        orderbook.match_order(buy_order).unwrap();
+
+        assert_eq!(orderbook.fill_estimate(OrderSide::Sell, 11, 5), 0.0);
+        assert_eq!(orderbook.fill_estimate(OrderSide::Buy, 10, 5), 0.0);
+    }
+
+    #[test]
+    fn zero_quantity_trade_not_produced() {
+        let mut orderbook = Orderbook::default();
+        let price = 1;
+
+        let exhausted_sell_order = Order::new(OrderType::Normal, OrderSide::Sell, price, 0, 0);
+        orderbook.insert_order(exhausted_sell_order).unwrap();
+
+        let buy_order = Order::new(OrderType::Normal, OrderSide::Buy, price, 1, 0);
+        let trades = orderbook.match_order(buy_order).unwrap();
+
+        assert!(trades.is_empty());
+        assert_book_has_order(&orderbook, &buy_order.id, &buy_order.side, &1, &price);
+    }
+
+    #[test]
+    fn can_cancel_where() {
+        let (market_data_sender, market_data_receiver) = channel::unbounded();
+        let mut orderbook = Orderbook::new(Some(market_data_sender));
+
+        let sell_order_1 = Order::new(OrderType::Normal, OrderSide::Sell, 10, 1, 0);
+        let sell_order_2 = Order::new(OrderType::Normal, OrderSide::Sell, 11, 1, 0);
+        let sell_order_3 = Order::new(OrderType::Normal, OrderSide::Sell, 12, 1, 0);
+        let buy_order = Order::new(OrderType::Normal, OrderSide::Buy, 5, 1, 0);
+
+        orderbook.match_order(sell_order_1).unwrap();
+        orderbook.match_order(sell_order_2).unwrap();
+        orderbook.match_order(sell_order_3).unwrap();
+        orderbook.match_order(buy_order).unwrap();
+
+        let cancelled_orders =
+            orderbook.cancel_where(|order| order.side == OrderSide::Sell && order.price > 10);
+
+        assert_eq!(cancelled_orders.len(), 2);
+        assert_eq!(market_data_receiver.try_iter().count(), 2);
+        assert!(!orderbook.orders.contains_key(&sell_order_2.id));
+        assert!(!orderbook.orders.contains_key(&sell_order_3.id));
+        assert!(orderbook.ask_levels.get_orders(&11).is_none());
+        assert!(orderbook.ask_levels.get_orders(&12).is_none());
+        assert_book_has_order(&orderbook, &sell_order_1.id, &sell_order_1.side, &1, &10);
+        assert_book_has_order(&orderbook, &buy_order.id, &buy_order.side, &1, &5);
+    }
+
+    #[test]
+    fn unbalanced_trade_rejected_at_commit() {
+        let mut orderbook = Orderbook::default();
+        let price = 1;
+
+        let sell_order = Order::new(OrderType::Normal, OrderSide::Sell, price, 2, 0);
+        orderbook.match_order(sell_order).unwrap();
+
+        let mut buy_order = Order::new(OrderType::Normal, OrderSide::Buy, price, 2, 0);
+        let unbalanced_trade = Trade {
+            bid: TradeInfo {
+                order_id: buy_order.id,
+                price,
+                quantity: 1,
+            },
+            ask: TradeInfo {
+                order_id: sell_order.id,
+                price,
+                quantity: 2,
+            },
+            aggressor_side: OrderSide::Buy,
+            resting_order_audit: TradeAudit {
+                queue_position: 0,
+                resting_duration_ms: 0,
+            },
+            effective_spread: None,
+        };
+
+        assert!(orderbook
+            .commit_trades(&mut buy_order, &vec![unbalanced_trade])
+            .is_err());
+        assert_book_has_order(&orderbook, &sell_order.id, &sell_order.side, &2, &price);
+        assert!(orderbook.recent_trades(1).is_empty());
+    }
+
+    fn fill(bid_id: Uuid, ask_id: Uuid, price: Price, quantity: Quantity) -> Trade {
+        Trade {
+            bid: TradeInfo {
+                order_id: bid_id,
+                price,
+                quantity,
+            },
+            ask: TradeInfo {
+                order_id: ask_id,
+                price,
+                quantity,
+            },
+            aggressor_side: OrderSide::Buy,
+            resting_order_audit: TradeAudit {
+                queue_position: 0,
+                resting_duration_ms: 0,
+            },
+            effective_spread: Some(price as f64),
+        }
+    }
+
+    #[test]
+    fn repeated_fills_between_pair_coalesced() {
+        let bid_id = Uuid::new_v4();
+        let first_ask_id = Uuid::new_v4();
+        let second_ask_id = Uuid::new_v4();
+        let trades = vec![
+            fill(bid_id, first_ask_id, 10, 1),
+            fill(bid_id, first_ask_id, 13, 2),
+            fill(bid_id, second_ask_id, 14, 1),
+            fill(bid_id, first_ask_id, 10, 1),
+        ];
+
+        let coalesced_trades = coalesce_fills(trades);
+
+        assert_eq!(
+            coalesced_trades,
+            vec![
+                Trade {
+                    effective_spread: Some(12.0),
+                    ..fill(bid_id, first_ask_id, 12, 3)
+                },
+                fill(bid_id, second_ask_id, 14, 1),
+                fill(bid_id, first_ask_id, 10, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn fills_against_different_orders_not_coalesced() {
+        let config = OrderbookConfig {
+            coalesce_fills: true,
+            ..Default::default()
+        };
+        let mut orderbook = Orderbook::with_config(None, config);
+
+        let first_ask = Order::new(OrderType::Normal, OrderSide::Sell, 10, 1, 0);
+        let second_ask = Order::new(OrderType::Normal, OrderSide::Sell, 10, 1, 0);
+        orderbook.match_order(first_ask).unwrap();
+        orderbook.match_order(second_ask).unwrap();
+
+        let buy_order = Order::new(OrderType::Normal, OrderSide::Buy, 10, 2, 0);
+        let trades = orderbook.match_order(buy_order).unwrap();
+
+        assert_eq!(trades.len(), 2);
+        assert_empty_book(&orderbook);
+    }
+
+    #[test]
+    fn repeated_fills_from_match_coalesced_and_recorded() {
+        let config = OrderbookConfig {
+            coalesce_fills: true,
+            ..Default::default()
+        };
+        let mut orderbook = Orderbook::with_config(None, config);
+        orderbook.max_fill_quantity = Some(1);
+
+        // Queued three times over, so a pass fills it once per queue entry
+        let sell_order = Order::new(OrderType::Normal, OrderSide::Sell, 10, 3, 0);
+        orderbook.match_order(sell_order).unwrap();
+        orderbook.insert_untracked_level_order(sell_order);
+        orderbook.insert_untracked_level_order(sell_order);
+
+        let buy_order = Order::new(OrderType::Normal, OrderSide::Buy, 10, 3, 0);
+        let trades = orderbook.match_order(buy_order).unwrap();
+
+        let [trade] = trades.as_slice() else {
+            panic!("Expected a single coalesced trade");
+        };
+        assert_eq!(trade.bid.order_id, buy_order.id);
+        assert_eq!(trade.ask.order_id, sell_order.id);
+        assert_eq!(trade.bid.quantity, 3);
+        assert_eq!(orderbook.recent_trades(10), trades);
+        assert!(!orderbook.orders.contains_key(&sell_order.id));
+    }
+
+    #[test]
+    fn lowering_minimum_quantity_keeps_priority() {
+        let config = OrderbookConfig {
+            keep_priority_on_minimum_quantity_decrease: true,
+            ..Default::default()
+        };
+        let mut orderbook = Orderbook::with_config(None, config);
+        let price = 10;
+
+        let buy_order_1 = Order::new(OrderType::Normal, OrderSide::Buy, price, 10, 5);
+        let buy_order_2 = Order::new(OrderType::Normal, OrderSide::Buy, price, 10, 0);
+        orderbook.match_order(buy_order_1).unwrap();
+        orderbook.match_order(buy_order_2).unwrap();
+
+        let modify_request = OrderRequest::Modify(TradeRequest {
+            id: buy_order_1.id,
+            minimum_quantity: 1,
+            ..TradeRequest::normal(OrderSide::Buy, price, 10)
+        });
+        let updates = orderbook.place_trade_request(modify_request).unwrap();
+        assert!(updates.is_empty());
+
+        let sell_order = Order::new(OrderType::Normal, OrderSide::Sell, price, 1, 0);
+        let trades = orderbook.match_order(sell_order).unwrap();
+
+        assert_eq!(trades.first().unwrap().bid.order_id, buy_order_1.id);
+        assert_eq!(
+            orderbook.bid_levels.get_order(price, 0),
+            Some(&buy_order_1.id)
+        );
+    }
+
+    #[test]
+    fn lowering_minimum_quantity_from_other_account_rejected() {
+        let config = OrderbookConfig {
+            keep_priority_on_minimum_quantity_decrease: true,
+            ..Default::default()
+        };
+        let mut orderbook = Orderbook::with_config(None, config);
+        let price = 10;
+
+        let buy_order = Order {
+            account_id: Some(Uuid::new_v4()),
+            ..Order::new(OrderType::Normal, OrderSide::Buy, price, 10, 5)
+        };
+        orderbook.match_order(buy_order).unwrap();
+
+        let modify_request = OrderRequest::Modify(TradeRequest {
+            id: buy_order.id,
+            minimum_quantity: 1,
+            account_id: Some(Uuid::new_v4()),
+            ..TradeRequest::normal(OrderSide::Buy, price, 10)
+        });
+        let updates = orderbook.place_trade_request(modify_request).unwrap();
+
+        assert!(matches!(
+            updates.as_slice(),
+            [MarketDataUpdate::OrderRejected { id, .. }] if *id == buy_order.id
+        ));
+        assert_eq!(orderbook.orders[&buy_order.id].minimum_quantity, 5);
+    }
+
+    #[test]
+    fn array_levels_match_like_tree_levels() {
+        let orders = [
+            Order::new(OrderType::Normal, OrderSide::Sell, 12, 2, 0),
+            Order::new(OrderType::Normal, OrderSide::Sell, 11, 1, 0),
+            Order::new(OrderType::Normal, OrderSide::Buy, 8, 3, 0),
+            Order::new(OrderType::Normal, OrderSide::Sell, 11, 2, 0),
+            Order::new(OrderType::Normal, OrderSide::Buy, 9, 1, 0),
+            Order::new(OrderType::Normal, OrderSide::Buy, 12, 4, 0),
+            Order::new(OrderType::Market, OrderSide::Sell, 0, 2, 0),
+            Order::new(OrderType::ImmediateOrCancel, OrderSide::Buy, 13, 5, 0),
+            Order::new(OrderType::Normal, OrderSide::Sell, 8, 1, 0),
+        ];
+
+        // Fixed clocks, so both books audit the same resting durations
+        let [tree_results, array_results] = level_backend_orderbooks().map(|mut orderbook| {
+            orderbook.set_time(0);
+            let trades: Vec<Vec<Trade>> = orders
+                .iter()
+                .map(|order| orderbook.match_order(*order).unwrap())
+                .collect();
+            (trades, orderbook.book_histogram(10), orderbook.quote())
+        });
+
+        assert!(tree_results.0.iter().any(|trades| trades.len() > 1));
+        assert_eq!(tree_results, array_results);
+    }
+
+    #[test]
+    fn order_outside_price_range_rejected() {
+        let [_, mut orderbook] = level_backend_orderbooks();
+        let is_out_of_range_error = |result: Result<Vec<Trade>>| {
+            result.is_err_and(|error| {
+                error.downcast_ref::<MatchError>() == Some(&MatchError::PriceOutOfRange)
+            })
+        };
+
+        let above_range = Order::new(OrderType::Normal, OrderSide::Buy, 101, 1, 0);
+        let below_range = Order::new(OrderType::Normal, OrderSide::Sell, -1, 1, 0);
+        assert!(is_out_of_range_error(orderbook.match_order(above_range)));
+        assert!(is_out_of_range_error(orderbook.match_order(below_range)));
+        assert_empty_book(&orderbook);
+
+        let market_order = Order::new(OrderType::Market, OrderSide::Buy, 1000, 1, 0);
+        assert!(orderbook.match_order(market_order).is_ok());
+    }
+
+    #[test]
+    fn off_tick_price_rejected() {
+        let config = OrderbookConfig {
+            price_range: Some(PriceRange::new(100, 200, 5).unwrap()),
+            ..Default::default()
+        };
+        let mut orderbook = Orderbook::with_config(None, config);
+
+        let off_tick_order = Order::new(OrderType::Normal, OrderSide::Buy, 102, 1, 0);
+        let on_tick_order = Order::new(OrderType::Normal, OrderSide::Buy, 105, 1, 0);
+
+        assert!(orderbook.match_order(off_tick_order).is_err());
+        assert!(orderbook.match_order(on_tick_order).is_ok());
+        assert_book_has_order(&orderbook, &on_tick_order.id, &OrderSide::Buy, &1, &105);
+    }
+
+    #[test]
+    fn invalid_price_range_rejected() {
+        assert_eq!(
+            PriceRange::new(0, 100, 0),
+            Err(PriceRangeError::NonPositiveTickSize)
+        );
+        assert_eq!(
+            PriceRange::new(100, 0, 1),
+            Err(PriceRangeError::MaxBelowMin)
+        );
+        assert_eq!(
+            PriceRange::new(Price::MIN, Price::MAX, 1),
+            Err(PriceRangeError::TooManyTicks)
+        );
+        assert!(PriceRange::new(100, 100, 1).is_ok());
+    }
+
+    #[test]
+    fn restore_outside_price_range_fails() {
+        let config = OrderbookConfig {
+            price_range: Some(PriceRange::new(0, 100, 1).unwrap()),
+            ..Default::default()
+        };
+        let mut order = Order::new(OrderType::Normal, OrderSide::Buy, 101, 1, 0);
+        order.sequence = 1;
+
+        let error = Orderbook::restore(None, config, vec![order]).err().unwrap();
+        assert_eq!(
+            error.downcast_ref::<MatchError>(),
+            Some(&MatchError::PriceOutOfRange)
+        );
+    }
+
+    #[test]
+    fn array_levels_track_best_price_like_tree_levels() {
+        let prices = [40, 60, 50, 45, 55];
+
+        let [tree_results, array_results] = level_backend_orderbooks().map(|mut orderbook| {
+            let orders: Vec<Order> = prices
+                .iter()
+                .flat_map(|price| {
+                    [
+                        Order::new(OrderType::Normal, OrderSide::Buy, *price, 1, 0),
+                        Order::new(OrderType::Normal, OrderSide::Sell, *price + 30, 1, 0),
+                    ]
+                })
+                .collect();
+            for order in &orders {
+                orderbook.match_order(*order).unwrap();
+            }
+
+            let mut quotes = vec![orderbook.quote()];
+            for price in prices {
+                orderbook.cancel_level(OrderSide::Buy, price, None);
+                orderbook.cancel_level(OrderSide::Sell, price + 30, None);
+                quotes.push(orderbook.quote());
+            }
+            quotes
+        });
+
+        assert_eq!(tree_results, array_results);
+    }
+
+    #[test]
+    fn empty_price_level_skipped_when_matching() {
+        let mut orderbook = Orderbook::default();
+        let _guard = METRICS_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        reset_metrics();
+
+        orderbook.ask_levels.insert_empty_level(10);
+        let sell_order = Order::new(OrderType::Normal, OrderSide::Sell, 11, 1, 0);
+        orderbook.match_order(sell_order).unwrap();
+
+        let buy_order = Order::new(OrderType::Normal, OrderSide::Buy, 11, 1, 0);
+        let trades = orderbook.match_order(buy_order).unwrap();
+
+        assert_eq!(trades.first().unwrap().ask.order_id, sell_order.id);
+        assert_empty_book(&orderbook);
+        assert_eq!(EMPTY_PRICE_LEVELS_SKIPPED.get(), 1);
+    }
+
+    #[test]
+    fn fill_below_min_notional_skipped() {
+        let config = OrderbookConfig {
+            min_notional: Some(30),
+            ..Default::default()
+        };
+        let mut orderbook = Orderbook::with_config(None, config);
+        let price = 10;
+
+        let sell_order = Order::new(OrderType::Normal, OrderSide::Sell, price, 5, 0);
+        orderbook.match_order(sell_order).unwrap();
+
+        let small_buy_order = Order::new(OrderType::Normal, OrderSide::Buy, price, 1, 0);
+        let small_trades = orderbook.match_order(small_buy_order).unwrap();
+
+        let large_buy_order = Order::new(OrderType::Normal, OrderSide::Buy, price, 5, 0);
+        let large_trades = orderbook.match_order(large_buy_order).unwrap();
+
+        assert!(small_trades.is_empty());
+        assert_eq!(large_trades.len(), 1);
+        assert_eq!(large_trades.first().unwrap().ask.quantity, 5);
+        assert_empty_asks(&orderbook);
+    }
+
+    #[test]
+    fn cancel_level_for_account_leaves_other_accounts() {
+        let mut orderbook = Orderbook::default();
+        let price = 1;
+        let account_id = Uuid::new_v4();
+
+        let account_order = Order {
+            account_id: Some(account_id),
+            ..Order::new(OrderType::Normal, OrderSide::Buy, price, 1, 0)
+        };
+        let other_account_order = Order {
+            account_id: Some(Uuid::new_v4()),
+            ..Order::new(OrderType::Normal, OrderSide::Buy, price, 2, 0)
+        };
+        let no_account_order = Order::new(OrderType::Normal, OrderSide::Buy, price, 3, 0);
+        for order in [account_order, other_account_order, no_account_order] {
+            orderbook.match_order(order).unwrap();
+        }
+
+        let (reply_sender, mut reply_receiver) = oneshot::channel();
+        orderbook
+            .place_trade_request(OrderRequest::CancelLevel(
+                CancelLevelRequest {
+                    side: OrderSide::Buy,
+                    price,
+                    account_id: Some(account_id),
+                },
+                reply_sender,
+            ))
+            .unwrap();
+
+        assert_eq!(reply_receiver.try_recv().unwrap(), vec![account_order.id]);
+        assert!(!orderbook.orders.contains_key(&account_order.id));
+        assert_book_has_order(
+            &orderbook,
+            &other_account_order.id,
+            &OrderSide::Buy,
+            &2,
+            &price,
+        );
+        assert_book_has_order(
+            &orderbook,
+            &no_account_order.id,
+            &OrderSide::Buy,
+            &3,
+            &price,
+        );
+    }
+
+    #[test]
+    fn can_cancel_many_orders() {
+        let mut orderbook = Orderbook::default();
+
+        let buy_order = Order::new(OrderType::Normal, OrderSide::Buy, 1, 1, 0);
+        let sell_order = Order::new(OrderType::Normal, OrderSide::Sell, 2, 1, 0);
+        orderbook.match_order(buy_order).unwrap();
+        orderbook.match_order(sell_order).unwrap();
+        let unknown_order_id = Uuid::new_v4();
+
+        let (reply_sender, mut reply_receiver) = oneshot::channel();
+        let updates = orderbook
+            .place_trade_request(OrderRequest::CancelMany(
+                vec![buy_order.id, unknown_order_id, sell_order.id],
+                reply_sender,
+            ))
+            .unwrap();
+
+        assert_eq!(
+            reply_receiver.try_recv().unwrap(),
+            vec![
+                CancelOrderResult {
+                    order_id: buy_order.id,
+                    found: true,
+                },
+                CancelOrderResult {
+                    order_id: unknown_order_id,
+                    found: false,
+                },
+                CancelOrderResult {
+                    order_id: sell_order.id,
+                    found: true,
+                },
+            ]
+        );
+        assert_eq!(updates.len(), 2);
+        assert_empty_book(&orderbook);
+    }
+
+    #[test]
+    fn book_histogram_for_five_prices() {
+        let mut orderbook = Orderbook::default();
+
+        for (side, price, quantity) in [
+            (OrderSide::Buy, 8, 1),
+            (OrderSide::Buy, 8, 2),
+            (OrderSide::Buy, 9, 3),
+            (OrderSide::Sell, 10, 4),
+            (OrderSide::Sell, 11, 5),
+            (OrderSide::Sell, 11, 6),
+            (OrderSide::Sell, 12, 7),
+        ] {
+            let order = Order::new(OrderType::Normal, side, price, quantity, 0);
+            orderbook.match_order(order).unwrap();
+        }
+
+        let book_level = |side, price, quantity, order_count| BookLevel {
+            side,
+            price,
+            quantity,
+            order_count,
+        };
+
+        assert_eq!(
+            orderbook.book_histogram(10),
+            vec![
+                book_level(OrderSide::Buy, 9, 3, 1),
+                book_level(OrderSide::Buy, 8, 3, 2),
+                book_level(OrderSide::Sell, 10, 4, 1),
+                book_level(OrderSide::Sell, 11, 11, 2),
+                book_level(OrderSide::Sell, 12, 7, 1),
+            ]
+        );
+        assert_eq!(orderbook.book_histogram(1).len(), 2);
+    }
+
+    #[test]
+    fn book_histogram_with_zero_levels_empty() {
+        let mut orderbook = Orderbook::default();
+
+        for side in [OrderSide::Buy, OrderSide::Sell] {
+            let price = if side == OrderSide::Buy { 1 } else { 2 };
+            let order = Order::new(OrderType::Normal, side, price, 1, 0);
+            orderbook.match_order(order).unwrap();
+        }
+
+        assert!(orderbook.book_histogram(0).is_empty());
+        assert_eq!(orderbook.book_histogram(usize::MAX).len(), 2);
+    }
+
+    fn query_order_status(orderbook: &mut Orderbook, order_id: Uuid) -> OrderStatusReport {
+        let (reply_sender, mut reply_receiver) = oneshot::channel();
+        orderbook
+            .place_trade_request(OrderRequest::Query(order_id, reply_sender))
+            .unwrap();
+        reply_receiver.try_recv().unwrap()
+    }
+
+    #[test]
+    fn order_status_of_resting_order() {
+        let mut orderbook = Orderbook::default();
+        let order = Order::new(OrderType::Normal, OrderSide::Buy, 5, 3, 0);
+        orderbook.match_order(order).unwrap();
+
+        assert_eq!(
+            query_order_status(&mut orderbook, order.id),
+            OrderStatusReport {
+                order_id: order.id,
+                status: OrderStatus::Resting,
+                side: Some(OrderSide::Buy),
+                price: Some(5),
+                initial_quantity: Some(3),
+                remaining_quantity: Some(3),
+            }
+        );
+    }
+
+    #[test]
+    fn order_status_of_partially_filled_order() {
+        let mut orderbook = Orderbook::default();
+        let sell_order = Order::new(OrderType::Normal, OrderSide::Sell, 5, 3, 0);
+        let buy_order = Order::new(OrderType::Normal, OrderSide::Buy, 5, 1, 0);
+        orderbook.match_order(sell_order).unwrap();
+        orderbook.match_order(buy_order).unwrap();
+
+        let report = query_order_status(&mut orderbook, sell_order.id);
+        assert_eq!(report.status, OrderStatus::PartiallyFilled);
+        assert_eq!(report.initial_quantity, Some(3));
+        assert_eq!(report.remaining_quantity, Some(2));
+
+        let filled_report = query_order_status(&mut orderbook, buy_order.id);
+        assert_eq!(filled_report.status, OrderStatus::Filled);
+        assert_eq!(filled_report.remaining_quantity, Some(0));
+    }
+
+    #[test]
+    fn order_status_of_filled_and_cancelled_orders() {
+        let mut orderbook = Orderbook::default();
+        let sell_order = Order::new(OrderType::Normal, OrderSide::Sell, 5, 1, 0);
+        let buy_order = Order::new(OrderType::Normal, OrderSide::Buy, 4, 2, 0);
+        let ioc_order = Order::new(OrderType::ImmediateOrCancel, OrderSide::Buy, 5, 3, 0);
+        orderbook.match_order(sell_order).unwrap();
+        orderbook.match_order(buy_order).unwrap();
+        orderbook.match_order(ioc_order).unwrap();
+        orderbook
+            .place_trade_request(OrderRequest::Cancel(CancelRequestType::User, buy_order.id))
+            .unwrap();
+
+        let filled_report = query_order_status(&mut orderbook, sell_order.id);
+        assert_eq!(filled_report.status, OrderStatus::Filled);
+        assert_eq!(filled_report.price, Some(5));
+
+        let ioc_report = query_order_status(&mut orderbook, ioc_order.id);
+        assert_eq!(ioc_report.status, OrderStatus::Cancelled);
+        assert_eq!(ioc_report.remaining_quantity, Some(2));
+
+        let cancelled_report = query_order_status(&mut orderbook, buy_order.id);
+        assert_eq!(cancelled_report.status, OrderStatus::Cancelled);
+        assert_eq!(cancelled_report.remaining_quantity, Some(2));
+    }
+
+    #[test]
+    fn oldest_terminal_status_evicted_past_capacity() {
+        let mut orderbook = Orderbook::default();
+        let orders: Vec<Order> = (0..=TERMINAL_STATUS_CAPACITY)
+            .map(|_| Order::new(OrderType::Normal, OrderSide::Buy, 5, 1, 0))
+            .collect();
+        for order in &orders {
+            orderbook.match_order(*order).unwrap();
+            orderbook.cancel_order(CancelRequestType::User, order.id);
+        }
+
+        assert_eq!(
+            orderbook.order_status(orders[0].id).status,
+            OrderStatus::NotFound
+        );
+        assert_eq!(
+            orderbook.order_status(orders[1].id).status,
+            OrderStatus::Cancelled
+        );
+        assert_eq!(orderbook.terminal_statuses.len(), TERMINAL_STATUS_CAPACITY);
+    }
+
+    #[test]
+    fn depth_snapshot_request_aggregates_each_side() {
+        let mut orderbook = Orderbook::default();
+
+        for (side, price, quantity) in [
+            (OrderSide::Buy, 8, 1),
+            (OrderSide::Buy, 9, 2),
+            (OrderSide::Buy, 9, 3),
+            (OrderSide::Sell, 10, 4),
+            (OrderSide::Sell, 12, 5),
+            (OrderSide::Sell, 11, 6),
+        ] {
+            let order = Order::new(OrderType::Normal, side, price, quantity, 0);
+            orderbook.match_order(order).unwrap();
+        }
+
+        let (reply_sender, mut reply_receiver) = oneshot::channel();
+        orderbook
+            .place_trade_request(OrderRequest::Snapshot(2, reply_sender))
+            .unwrap();
+
+        let book_level = |side, price, quantity, order_count| BookLevel {
+            side,
+            price,
+            quantity,
+            order_count,
+        };
+        assert_eq!(
+            reply_receiver.try_recv().unwrap(),
+            DepthSnapshot {
+                bids: vec![
+                    book_level(OrderSide::Buy, 9, 5, 2),
+                    book_level(OrderSide::Buy, 8, 1, 1),
+                ],
+                asks: vec![
+                    book_level(OrderSide::Sell, 10, 4, 1),
+                    book_level(OrderSide::Sell, 11, 6, 1),
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn level_summary_ages_front_order() {
+        let mut orderbook = Orderbook::default();
+
+        orderbook.set_time(1_000);
+        let front_buy_order = Order::new(OrderType::Normal, OrderSide::Buy, 9, 1, 0);
+        orderbook.match_order(front_buy_order).unwrap();
+
+        orderbook.set_time(1_500);
+        let back_buy_order = Order::new(OrderType::Normal, OrderSide::Buy, 9, 2, 0);
+        let sell_order = Order::new(OrderType::Normal, OrderSide::Sell, 11, 3, 0);
+        orderbook.match_order(back_buy_order).unwrap();
+        orderbook.match_order(sell_order).unwrap();
+
+        orderbook.set_time(2_000);
+        assert_eq!(
+            orderbook.level_summary(),
+            vec![
+                LevelSummary {
+                    level: BookLevel {
+                        side: OrderSide::Buy,
+                        price: 9,
+                        quantity: 3,
+                        order_count: 2,
+                    },
+                    oldest_order_age_ms: 1_000,
+                },
+                LevelSummary {
+                    level: BookLevel {
+                        side: OrderSide::Sell,
+                        price: 11,
+                        quantity: 3,
+                        order_count: 1,
+                    },
+                    oldest_order_age_ms: 500,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn modify_carries_filled_quantity_without_self_matching() {
+        let mut orderbook = Orderbook::new(None);
+
+        let buy_order = Order::new(OrderType::Normal, OrderSide::Buy, 10, 10, 0);
+        let sell_order = Order::new(OrderType::Normal, OrderSide::Sell, 10, 4, 0);
+        orderbook.match_order(buy_order).unwrap();
+        orderbook.match_order(sell_order).unwrap();
+
+        let increased_order = Order {
+            price: 11,
+            initial_quantity: 20,
+            ..buy_order
+        };
+        let (_, trades) = orderbook.modify_order(increased_order).unwrap();
+        assert!(trades.is_empty());
+        assert_book_has_order(&orderbook, &buy_order.id, &buy_order.side, &16, &11);
+
+        let decreased_order = Order {
+            price: 11,
+            initial_quantity: 8,
+            ..buy_order
+        };
+        let (_, trades) = orderbook.modify_order(decreased_order).unwrap();
+        assert!(trades.is_empty());
+        assert_book_has_order(&orderbook, &buy_order.id, &buy_order.side, &4, &11);
+
+        let opposite_side_order = Order {
+            side: OrderSide::Sell,
+            initial_quantity: 8,
+            ..buy_order
+        };
+        assert!(orderbook.modify_order(opposite_side_order).is_err());
+        assert_book_has_order(&orderbook, &buy_order.id, &buy_order.side, &4, &11);
+    }
+
+    #[test]
+    fn modify_quantity_to_filled_removes_order() {
+        let mut orderbook = Orderbook::new(None);
+
+        let buy_order = Order::new(OrderType::Normal, OrderSide::Buy, 10, 10, 0);
+        let sell_order = Order::new(OrderType::Normal, OrderSide::Sell, 10, 4, 0);
+        orderbook.match_order(buy_order).unwrap();
+        orderbook.match_order(sell_order).unwrap();
+
+        let filled_order = Order {
+            initial_quantity: 4,
+            ..buy_order
+        };
+        let (cancelled_order, trades) = orderbook.modify_order(filled_order).unwrap();
+
+        assert_eq!(cancelled_order.order.id, buy_order.id);
+        assert!(trades.is_empty());
+        assert_empty_book(&orderbook);
+    }
+
+    fn warming_up_orderbook() -> Orderbook {
+        let config = OrderbookConfig {
+            warmup_millis: Some(60_000),
+            ..Default::default()
+        };
+        let mut orderbook = Orderbook::with_config(None, config);
+        orderbook.set_time(Utc::now().timestamp_millis());
+        orderbook
+    }
+
+    fn is_warmup_rejection(order_id: Uuid, result: Result<Vec<MarketDataUpdate>>) -> bool {
+        result.is_ok_and(|market_updates| {
+            market_updates
+                == vec![MarketDataUpdate::OrderRejected {
+                    id: order_id,
+                    reason: MatchError::Warmup.to_string(),
+                }]
+        })
+    }
+
+    #[test]
+    fn orders_rejected_until_ready() {
+        let mut orderbook = warming_up_orderbook();
+        let order_id = Uuid::new_v4();
+
+        assert!(is_warmup_rejection(
+            order_id,
+            orderbook.place_trade_request(upsert_request(order_id, 1))
+        ));
+        assert!(orderbook
+            .place_trade_request(OrderRequest::Cancel(CancelRequestType::User, order_id))
+            .is_ok());
+        assert!(orderbook.orders.is_empty());
+        assert_eq!(orderbook.recent_rejections(1)[0].order_id, order_id);
+
+        orderbook.mark_ready();
+
+        assert!(orderbook
+            .place_trade_request(upsert_request(order_id, 1))
+            .is_ok());
+        assert_book_has_order(&orderbook, &order_id, &OrderSide::Buy, &1, &1);
+    }
+
+    #[test]
+    fn orders_accepted_after_warmup_elapses() {
+        let mut orderbook = warming_up_orderbook();
+        let order_id = Uuid::new_v4();
+
+        assert!(is_warmup_rejection(
+            order_id,
+            orderbook.place_trade_request(upsert_request(order_id, 1))
+        ));
+
+        orderbook.set_time(Utc::now().timestamp_millis() + 120_000);
+
+        assert!(orderbook
+            .place_trade_request(upsert_request(order_id, 1))
+            .is_ok());
+        assert_book_has_order(&orderbook, &order_id, &OrderSide::Buy, &1, &1);
+    }
+
+    #[test]
+    fn config_request_reports_custom_config() {
+        let config = OrderbookConfig {
+            min_notional: Some(100),
+            cross_rule: CrossRule::StrictlyBetter,
+            warmup_millis: Some(60_000),
+            ..Default::default()
+        };
+        let mut orderbook = Orderbook::with_config(None, config);
+        orderbook.set_time(Utc::now().timestamp_millis());
+
+        let (reply_sender, mut reply_receiver) = oneshot::channel();
+        orderbook
+            .place_trade_request(OrderRequest::Config(reply_sender))
+            .unwrap();
+
+        assert_eq!(
+            reply_receiver.try_recv().unwrap(),
+            EffectiveConfig {
+                orderbook: config,
+                warming_up: true,
+            }
+        );
+    }
+
+    #[test]
+    fn cancellation_reason_distinguishes_expiry_from_user() {
+        let mut orderbook = Orderbook::default();
+
+        let expiring_order = Order::new(OrderType::Normal, OrderSide::Buy, 1, 1, 0);
+        let user_cancelled_order = Order::new(OrderType::Normal, OrderSide::Buy, 1, 1, 0);
+        orderbook.match_order(expiring_order).unwrap();
+        orderbook.match_order(user_cancelled_order).unwrap();
+
+        let expiry_updates = orderbook
+            .place_trade_request(OrderRequest::Cancel(
+                CancelRequestType::Expiry,
+                expiring_order.id,
+            ))
+            .unwrap();
+        let user_updates = orderbook
+            .place_trade_request(OrderRequest::Cancel(
+                CancelRequestType::User,
+                user_cancelled_order.id,
+            ))
+            .unwrap();
+
+        let cancel_request_type = |updates: &[MarketDataUpdate]| match updates.first() {
+            Some(MarketDataUpdate::Cancellation(cancelled_order)) => {
+                cancelled_order.cancel_request_type.clone()
+            }
+            _ => panic!("Expected a cancellation update"),
+        };
+        assert_eq!(
+            cancel_request_type(&expiry_updates),
+            CancelRequestType::Expiry
+        );
+        assert_eq!(cancel_request_type(&user_updates), CancelRequestType::User);
+    }
+
+    fn self_trade_preventing_orderbook(self_trade_prevention: SelfTradePrevention) -> Orderbook {
+        let config = OrderbookConfig {
+            self_trade_prevention,
+            ..Default::default()
+        };
+        Orderbook::with_config(None, config)
+    }
+
+    fn account_order(side: OrderSide, account_id: Uuid) -> Order {
+        Order {
+            account_id: Some(account_id),
+            ..Order::new(OrderType::Normal, side, 10, 1, 0)
+        }
+    }
+
+    #[test]
+    fn skip_opposing_prevents_same_account_trade() {
+        let mut orderbook = self_trade_preventing_orderbook(SelfTradePrevention::SkipOpposing);
+        let account_id = Uuid::new_v4();
+
+        let ask = account_order(OrderSide::Sell, account_id);
+        let bid = account_order(OrderSide::Buy, account_id);
+        orderbook.match_order(ask).unwrap();
+        let trades = orderbook.match_order(bid).unwrap();
+
+        assert!(trades.is_empty());
+        assert_book_has_order(&orderbook, &ask.id, &OrderSide::Sell, &1, &10);
+        // The newer order is cancelled rather than resting across its own ask
+        assert!(!orderbook.orders.contains_key(&bid.id));
+        assert_empty_bids(&orderbook);
+        assert!(orderbook.quote().spread.is_none_or(|spread| spread > 0));
+    }
+
+    #[test]
+    fn skip_opposing_cancels_remainder_after_other_account_fills() {
+        let mut orderbook = self_trade_preventing_orderbook(SelfTradePrevention::SkipOpposing);
+        let account_id = Uuid::new_v4();
+
+        let own_ask = account_order(OrderSide::Sell, account_id);
+        let other_ask = account_order(OrderSide::Sell, Uuid::new_v4());
+        orderbook.match_order(own_ask).unwrap();
+        orderbook.match_order(other_ask).unwrap();
+        let bid = Order {
+            account_id: Some(account_id),
+            ..Order::new(OrderType::Normal, OrderSide::Buy, 10, 2, 0)
+        };
+        let trades = orderbook.match_order(bid).unwrap();
+
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].ask.order_id, other_ask.id);
+        assert!(!orderbook.orders.contains_key(&bid.id));
+        assert_book_has_order(&orderbook, &own_ask.id, &OrderSide::Sell, &1, &10);
+    }
+
+    #[test]
+    fn skip_opposing_rests_order_not_crossing_own_order() {
+        let mut orderbook = self_trade_preventing_orderbook(SelfTradePrevention::SkipOpposing);
+        let account_id = Uuid::new_v4();
+
+        let own_ask = account_order(OrderSide::Sell, account_id);
+        let bid = Order {
+            account_id: Some(account_id),
+            ..Order::new(OrderType::Normal, OrderSide::Buy, 9, 1, 0)
+        };
+        orderbook.match_order(own_ask).unwrap();
+        orderbook.match_order(bid).unwrap();
+
+        assert_book_has_order(&orderbook, &bid.id, &OrderSide::Buy, &1, &9);
+    }
+
+    #[test]
+    fn skip_opposing_matches_other_accounts_behind_own_order() {
+        let mut orderbook = self_trade_preventing_orderbook(SelfTradePrevention::SkipOpposing);
+        let account_id = Uuid::new_v4();
+
+        let own_ask = account_order(OrderSide::Sell, account_id);
+        let other_ask = account_order(OrderSide::Sell, Uuid::new_v4());
+        orderbook.match_order(own_ask).unwrap();
+        orderbook.match_order(other_ask).unwrap();
+        let trades = orderbook
+            .match_order(account_order(OrderSide::Buy, account_id))
+            .unwrap();
+
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].ask.order_id, other_ask.id);
+        assert_book_has_order(&orderbook, &own_ask.id, &OrderSide::Sell, &1, &10);
+    }
+
+    #[test]
+    fn cancel_resting_cancels_same_account_order() {
+        let mut orderbook = self_trade_preventing_orderbook(SelfTradePrevention::CancelResting);
+        let account_id = Uuid::new_v4();
+
+        let ask = account_order(OrderSide::Sell, account_id);
+        let bid = account_order(OrderSide::Buy, account_id);
+        orderbook.match_order(ask).unwrap();
+        let trades = orderbook.match_order(bid).unwrap();
+
+        assert!(trades.is_empty());
+        assert!(!orderbook.orders.contains_key(&ask.id));
+        assert_book_has_order(&orderbook, &bid.id, &OrderSide::Buy, &1, &10);
+    }
+
+    fn account_request(
+        order_type: OrderType,
+        side: OrderSide,
+        quantity: Quantity,
+        account_id: Uuid,
+    ) -> OrderRequest {
+        OrderRequest::Trade(TradeRequest {
+            order_type,
+            account_id: Some(account_id),
+            ..TradeRequest::normal(side, 10, quantity)
+        })
+    }
+
+    #[test]
+    fn cancel_resting_cancellation_returned_with_match_updates() {
+        let mut orderbook = self_trade_preventing_orderbook(SelfTradePrevention::CancelResting);
+        let account_id = Uuid::new_v4();
+        let ask = account_order(OrderSide::Sell, account_id);
+        orderbook.match_order(ask).unwrap();
+
+        let market_updates = orderbook
+            .place_trade_request(account_request(
+                OrderType::Normal,
+                OrderSide::Buy,
+                1,
+                account_id,
+            ))
+            .unwrap();
+
+        match market_updates.as_slice() {
+            [MarketDataUpdate::Cancellation(cancelled_order)] => {
+                assert_eq!(cancelled_order.order.id, ask.id);
+                assert_eq!(
+                    cancelled_order.cancel_request_type,
+                    CancelRequestType::SelfTradePrevention
+                );
+            }
+            _ => panic!("Expected only the self trade cancellation"),
+        }
+    }
+
+    #[test]
+    fn cancel_resting_keeps_own_order_when_trades_discarded() {
+        let mut orderbook = self_trade_preventing_orderbook(SelfTradePrevention::CancelResting);
+        let account_id = Uuid::new_v4();
+        let own_ask = account_order(OrderSide::Sell, account_id);
+        let other_ask = account_order(OrderSide::Sell, Uuid::new_v4());
+        orderbook.match_order(own_ask).unwrap();
+        orderbook.match_order(other_ask).unwrap();
+
+        // Can only fill 1 of 2 from the other account, so the kill discards its trades
+        let market_updates = orderbook
+            .place_trade_request(account_request(
+                OrderType::Kill,
+                OrderSide::Buy,
+                2,
+                account_id,
+            ))
+            .unwrap();
+
+        assert!(market_updates.is_empty());
+        assert_book_has_order(&orderbook, &own_ask.id, &OrderSide::Sell, &1, &10);
+        assert_book_has_order(&orderbook, &other_ask.id, &OrderSide::Sell, &1, &10);
+    }
+
+    #[derive(Debug)]
+    struct NotionalLimitCreditCheck {
+        max_notional: Price,
+    }
+
+    impl CreditCheck for NotionalLimitCreditCheck {
+        fn check(&self, order: &Order) -> Result<(), CreditError> {
+            match order.price * order.initial_quantity as Price > self.max_notional {
+                true => Err(CreditError::LimitExceeded),
+                false => Ok(()),
+            }
+        }
+    }
+
+    #[test]
+    fn credit_check_rejects_order_over_notional_limit() {
+        let mut orderbook = Orderbook::default();
+        orderbook.set_credit_check(Box::new(NotionalLimitCreditCheck { max_notional: 100 }));
+
+        let within_limit_order = Order::new(OrderType::Normal, OrderSide::Buy, 10, 10, 0);
+        let over_limit_order = Order::new(OrderType::Normal, OrderSide::Buy, 10, 11, 0);
+
+        assert!(orderbook.match_order(within_limit_order).is_ok());
+        let over_limit_result = orderbook.match_order(over_limit_order);
+
+        assert!(over_limit_result.is_err_and(|error| {
+            error.downcast_ref::<MatchError>()
+                == Some(&MatchError::Credit(CreditError::LimitExceeded))
+        }));
+        assert_book_has_order(
+            &orderbook,
+            &within_limit_order.id,
+            &within_limit_order.side,
+            &10,
+            &10,
+        );
+        assert!(!orderbook.orders.contains_key(&over_limit_order.id));
+    }
+
+    #[test]
+    fn modify_failing_credit_check_leaves_original_resting() {
+        let mut orderbook = Orderbook::default();
+        orderbook.set_credit_check(Box::new(NotionalLimitCreditCheck { max_notional: 100 }));
+        let order_id = Uuid::new_v4();
+        orderbook
+            .place_trade_request(OrderRequest::Trade(buy_request(order_id, 10)))
+            .unwrap();
+
+        let market_updates = orderbook
+            .place_trade_request(OrderRequest::Modify(TradeRequest {
+                id: order_id,
+                ..TradeRequest::normal(OrderSide::Buy, 10, 11)
+            }))
+            .unwrap();
+
+        assert_eq!(
+            market_updates,
+            vec![MarketDataUpdate::OrderRejected {
+                id: order_id,
+                reason: MatchError::Credit(CreditError::LimitExceeded).to_string(),
+            }]
+        );
+        assert_book_has_order(&orderbook, &order_id, &OrderSide::Buy, &1, &10);
+    }
+
+    #[test]
+    fn replace_to_price_outside_range_leaves_original_resting() {
+        let [_, mut orderbook] = level_backend_orderbooks();
+        let order_id = Uuid::new_v4();
+        orderbook
+            .place_trade_request(OrderRequest::Trade(buy_request(order_id, 10)))
+            .unwrap();
+
+        let replacement_request = TradeRequest::normal(OrderSide::Buy, 101, 1);
+        let replacement_id = replacement_request.id;
+        let market_updates = orderbook
+            .place_trade_request(OrderRequest::Replace(order_id, replacement_request))
+            .unwrap();
+
+        assert_eq!(
+            market_updates,
+            vec![MarketDataUpdate::OrderRejected {
+                id: replacement_id,
+                reason: MatchError::PriceOutOfRange.to_string(),
+            }]
+        );
+        assert_book_has_order(&orderbook, &order_id, &OrderSide::Buy, &1, &10);
+    }
 }
@@ -5,16 +5,19 @@ use std::{
 
 use uuid::Uuid;
 
-use super::Price;
+use super::{MatchError, Price, PriceRange};
 
 pub trait OrderLevels {
     fn new() -> Self;
-    fn insert_order(&mut self, price: Price, order_id: Uuid);
+    /// Fails with MatchError::PriceOutOfRange if the levels can't hold price
+    fn insert_order(&mut self, price: Price, order_id: Uuid) -> Result<(), MatchError>;
     fn remove_order(&mut self, price: &Price, order_id: &Uuid) -> bool;
     fn get_order(&self, price: Price, offset: usize) -> Option<&Uuid>;
     fn get_prices(&self) -> Vec<&Price>;
     fn get_best_price(&self) -> Option<&Price>;
     fn get_orders(&self, price: &Price) -> Option<&VecDeque<Uuid>>;
+    /// Zero based place of an order in its price level's queue
+    fn order_position(&self, price: &Price, order_id: &Uuid) -> Option<usize>;
     fn remove_empty_levels(&mut self);
 }
 
@@ -69,92 +72,318 @@ where
     fn get_orders(&self, key: &K) -> Option<&VecDeque<Uuid>> {
         self.levels.get(key)
     }
+
+    fn order_position(&self, key: &K, order_id: &Uuid) -> Option<usize> {
+        self.levels
+            .get(key)
+            .and_then(|orders| orders.iter().position(|x| x == order_id))
+    }
+}
+
+/// Price levels in a flat array indexed by tick, for a bounded range of
+/// dense prices. Emptied levels stay in place but are never reported
+///
+/// The lowest and highest occupied indexes are tracked, so the best
+/// price is found without scanning and only occupied spans are walked
+#[derive(Debug)]
+struct ArrayOrderLevels {
+    price_range: PriceRange,
+    prices: Vec<Price>,
+    levels: Vec<VecDeque<Uuid>>,
+    /// Lowest and highest indexes of levels with orders, None when empty
+    occupied: Option<(usize, usize)>,
+    /// Whether the highest price is the best, as it is for bids
+    descending: bool,
+}
+
+impl ArrayOrderLevels {
+    fn new(price_range: PriceRange, descending: bool) -> Self {
+        let prices: Vec<Price> = (0..price_range.tick_count())
+            .map(|tick| price_range.min_price() + tick as Price * price_range.tick_size())
+            .collect();
+        let levels = vec![VecDeque::new(); prices.len()];
+        Self {
+            price_range,
+            prices,
+            levels,
+            occupied: None,
+            descending,
+        }
+    }
+
+    fn index(&self, price: &Price) -> Option<usize> {
+        self.price_range.contains(*price).then(|| {
+            ((price - self.price_range.min_price()) / self.price_range.tick_size()) as usize
+        })
+    }
+
+    fn insert_order(&mut self, price: Price, order_id: Uuid) -> Result<(), MatchError> {
+        let index = self.index(&price).ok_or(MatchError::PriceOutOfRange)?;
+        self.levels[index].push_back(order_id);
+        self.occupied = match self.occupied {
+            Some((lowest, highest)) => Some((lowest.min(index), highest.max(index))),
+            None => Some((index, index)),
+        };
+        Ok(())
+    }
+
+    fn remove_order(&mut self, price: &Price, order_id: &Uuid) -> bool {
+        if let Some(index) = self.index(price) {
+            let orders = &mut self.levels[index];
+            if let Some(position) = orders.iter().position(|x| x == order_id) {
+                orders.remove(position);
+                if orders.is_empty() {
+                    self.shrink_occupied(index);
+                }
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Narrows the occupied span after the level at index empties,
+    /// walking inwards to the next level with orders
+    fn shrink_occupied(&mut self, index: usize) {
+        let Some((lowest, highest)) = self.occupied else {
+            return;
+        };
+        let is_occupied = |index: &usize| !self.levels[*index].is_empty();
+        self.occupied = match (index == lowest, index == highest) {
+            (true, true) => None,
+            (true, false) => (lowest..=highest)
+                .find(is_occupied)
+                .map(|lowest| (lowest, highest)),
+            (false, true) => (lowest..=highest)
+                .rev()
+                .find(is_occupied)
+                .map(|highest| (lowest, highest)),
+            (false, false) => Some((lowest, highest)),
+        };
+    }
+
+    fn get_order(&self, price: Price, offset: usize) -> Option<&Uuid> {
+        self.get_orders(&price).and_then(|orders| orders.get(offset))
+    }
+
+    /// Prices with orders, lowest first
+    fn occupied_prices(&self) -> impl DoubleEndedIterator<Item = &Price> {
+        let span = match self.occupied {
+            Some((lowest, highest)) => lowest..highest + 1,
+            None => 0..0,
+        };
+        self.prices[span.clone()]
+            .iter()
+            .zip(&self.levels[span])
+            .filter(|(_, orders)| !orders.is_empty())
+            .map(|(price, _)| price)
+    }
+
+    fn get_prices(&self) -> Vec<&Price> {
+        match self.descending {
+            true => self.occupied_prices().rev().collect(),
+            false => self.occupied_prices().collect(),
+        }
+    }
+
+    fn get_best_price(&self) -> Option<&Price> {
+        self.occupied.map(|(lowest, highest)| match self.descending {
+            true => &self.prices[highest],
+            false => &self.prices[lowest],
+        })
+    }
+
+    fn get_orders(&self, price: &Price) -> Option<&VecDeque<Uuid>> {
+        self.index(price)
+            .map(|index| &self.levels[index])
+            .filter(|orders| !orders.is_empty())
+    }
+
+    fn order_position(&self, price: &Price, order_id: &Uuid) -> Option<usize> {
+        self.get_orders(price)
+            .and_then(|orders| orders.iter().position(|x| x == order_id))
+    }
+}
+
+/// Storage behind a side's price levels, a tree unless
+/// the orderbook is configured with a price range
+#[derive(Debug)]
+enum LevelStorage<K> {
+    Tree(GenericOrderLevels<K>),
+    Array(ArrayOrderLevels),
 }
 
 #[derive(Debug)]
 pub struct AskOrderLevels {
-    inner: GenericOrderLevels<Price>,
+    inner: LevelStorage<Price>,
 }
 
 impl OrderLevels for AskOrderLevels {
     fn new() -> Self {
         Self {
-            inner: GenericOrderLevels::new(),
+            inner: LevelStorage::Tree(GenericOrderLevels::new()),
         }
     }
 
-    fn insert_order(&mut self, price: Price, order_id: Uuid) {
-        self.inner.insert_order(price, order_id);
+    fn insert_order(&mut self, price: Price, order_id: Uuid) -> Result<(), MatchError> {
+        match &mut self.inner {
+            LevelStorage::Tree(levels) => {
+                levels.insert_order(price, order_id);
+                Ok(())
+            }
+            LevelStorage::Array(levels) => levels.insert_order(price, order_id),
+        }
     }
 
     fn remove_order(&mut self, price: &Price, order_id: &Uuid) -> bool {
-        self.inner.remove_order(price, order_id)
+        match &mut self.inner {
+            LevelStorage::Tree(levels) => levels.remove_order(price, order_id),
+            LevelStorage::Array(levels) => levels.remove_order(price, order_id),
+        }
     }
 
     fn get_order(&self, price: Price, offset: usize) -> Option<&Uuid> {
-        self.inner.get_order(price, offset)
+        match &self.inner {
+            LevelStorage::Tree(levels) => levels.get_order(price, offset),
+            LevelStorage::Array(levels) => levels.get_order(price, offset),
+        }
     }
 
     fn get_prices(&self) -> Vec<&Price> {
-        self.inner.get_prices()
+        match &self.inner {
+            LevelStorage::Tree(levels) => levels.get_prices(),
+            LevelStorage::Array(levels) => levels.get_prices(),
+        }
     }
 
     fn get_best_price(&self) -> Option<&Price> {
-        self.inner.get_best_price()
+        match &self.inner {
+            LevelStorage::Tree(levels) => levels.get_best_price(),
+            LevelStorage::Array(levels) => levels.get_best_price(),
+        }
     }
 
     fn get_orders(&self, price: &Price) -> Option<&VecDeque<Uuid>> {
-        self.inner.get_orders(price)
+        match &self.inner {
+            LevelStorage::Tree(levels) => levels.get_orders(price),
+            LevelStorage::Array(levels) => levels.get_orders(price),
+        }
+    }
+
+    fn order_position(&self, price: &Price, order_id: &Uuid) -> Option<usize> {
+        match &self.inner {
+            LevelStorage::Tree(levels) => levels.order_position(price, order_id),
+            LevelStorage::Array(levels) => levels.order_position(price, order_id),
+        }
     }
 
     fn remove_empty_levels(&mut self) {
-        self.inner.levels.retain(|_, orders| !orders.is_empty());
+        // Array levels never report empty levels, so there's nothing to remove
+        if let LevelStorage::Tree(levels) = &mut self.inner {
+            levels.levels.retain(|_, orders| !orders.is_empty());
+        }
+    }
+}
+
+impl AskOrderLevels {
+    /// Levels in a flat array over price_range, lowest price first
+    pub fn with_price_range(price_range: PriceRange) -> Self {
+        Self {
+            inner: LevelStorage::Array(ArrayOrderLevels::new(price_range, false)),
+        }
+    }
+
+    /// Adds a price level with no orders, as a stale level would be
+    #[cfg(test)]
+    pub(crate) fn insert_empty_level(&mut self, price: Price) {
+        if let LevelStorage::Tree(levels) = &mut self.inner {
+            levels.levels.entry(price).or_default();
+        }
     }
 }
 
 #[derive(Debug)]
 pub struct BidOrderLevels {
-    inner: GenericOrderLevels<Reverse<Price>>,
+    inner: LevelStorage<Reverse<Price>>,
 }
 
 impl OrderLevels for BidOrderLevels {
     fn new() -> Self {
         Self {
-            inner: GenericOrderLevels::new(),
+            inner: LevelStorage::Tree(GenericOrderLevels::new()),
         }
     }
 
-    fn insert_order(&mut self, price: Price, order_id: Uuid) {
-        self.inner.insert_order(Reverse(price), order_id);
+    fn insert_order(&mut self, price: Price, order_id: Uuid) -> Result<(), MatchError> {
+        match &mut self.inner {
+            LevelStorage::Tree(levels) => {
+                levels.insert_order(Reverse(price), order_id);
+                Ok(())
+            }
+            LevelStorage::Array(levels) => levels.insert_order(price, order_id),
+        }
     }
 
     fn remove_order(&mut self, price: &Price, order_id: &Uuid) -> bool {
-        self.inner.remove_order(&Reverse(*price), order_id)
+        match &mut self.inner {
+            LevelStorage::Tree(levels) => levels.remove_order(&Reverse(*price), order_id),
+            LevelStorage::Array(levels) => levels.remove_order(price, order_id),
+        }
     }
 
     fn get_order(&self, price: Price, offset: usize) -> Option<&Uuid> {
-        self.inner.get_order(Reverse(price), offset)
+        match &self.inner {
+            LevelStorage::Tree(levels) => levels.get_order(Reverse(price), offset),
+            LevelStorage::Array(levels) => levels.get_order(price, offset),
+        }
     }
 
     fn get_prices(&self) -> Vec<&Price> {
-        self.inner
-            .get_prices()
-            .into_iter()
-            .map(|reverse_price| &reverse_price.0)
-            .collect()
+        match &self.inner {
+            LevelStorage::Tree(levels) => levels
+                .get_prices()
+                .into_iter()
+                .map(|reverse_price| &reverse_price.0)
+                .collect(),
+            LevelStorage::Array(levels) => levels.get_prices(),
+        }
     }
 
     fn get_best_price(&self) -> Option<&Price> {
-        self.inner
-            .get_best_price()
-            .map(|reverse_price| &reverse_price.0)
+        match &self.inner {
+            LevelStorage::Tree(levels) => levels
+                .get_best_price()
+                .map(|reverse_price| &reverse_price.0),
+            LevelStorage::Array(levels) => levels.get_best_price(),
+        }
     }
 
     fn get_orders(&self, price: &Price) -> Option<&VecDeque<Uuid>> {
-        self.inner.get_orders(&Reverse(*price))
+        match &self.inner {
+            LevelStorage::Tree(levels) => levels.get_orders(&Reverse(*price)),
+            LevelStorage::Array(levels) => levels.get_orders(price),
+        }
+    }
+
+    fn order_position(&self, price: &Price, order_id: &Uuid) -> Option<usize> {
+        match &self.inner {
+            LevelStorage::Tree(levels) => levels.order_position(&Reverse(*price), order_id),
+            LevelStorage::Array(levels) => levels.order_position(price, order_id),
+        }
     }
 
     fn remove_empty_levels(&mut self) {
-        self.inner.levels.retain(|_, orders| !orders.is_empty());
+        // Array levels never report empty levels, so there's nothing to remove
+        if let LevelStorage::Tree(levels) = &mut self.inner {
+            levels.levels.retain(|_, orders| !orders.is_empty());
+        }
+    }
+}
+
+impl BidOrderLevels {
+    /// Levels in a flat array over price_range, highest price first
+    pub fn with_price_range(price_range: PriceRange) -> Self {
+        Self {
+            inner: LevelStorage::Array(ArrayOrderLevels::new(price_range, true)),
+        }
     }
 }
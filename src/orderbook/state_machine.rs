@@ -0,0 +1,218 @@
+use crate::web_server::OrderRequest;
+
+use super::{orderbook::Orderbook, MarketDataUpdate, OrderbookConfig};
+
+/// Input to the matching state machine
+pub enum MatchingEvent {
+    OrderRequest(Box<OrderRequest>),
+    /// Advances the clock to a millisecond timestamp
+    Time(i64),
+}
+
+/// Orderbook driven purely by events
+///
+/// Has no channels and takes time only from events, so the
+/// same event sequence always produces the same updates
+pub struct MatchingStateMachine {
+    orderbook: Orderbook,
+}
+
+impl MatchingStateMachine {
+    pub fn new(config: OrderbookConfig) -> Self {
+        let mut orderbook = Orderbook::with_config(None, config);
+        orderbook.set_time(0);
+        Self { orderbook }
+    }
+
+    pub fn apply(&mut self, event: MatchingEvent) -> Vec<MarketDataUpdate> {
+        match event {
+            MatchingEvent::OrderRequest(order_request) => self
+                .orderbook
+                .place_trade_request(*order_request)
+                .unwrap_or_default(),
+            MatchingEvent::Time(timestamp_millis) => {
+                self.orderbook.set_time(timestamp_millis);
+                vec![]
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use uuid::Uuid;
+
+    use crate::{
+        orderbook::{CancelledOrder, Order, OrderSide, OrderType, Trade, TradeAudit, TradeInfo},
+        web_server::{CancelRequestType, TradeRequest},
+    };
+
+    use super::*;
+
+    fn trade_request(id: Uuid, order_side: OrderSide, price: i64, quantity: u64) -> TradeRequest {
+        TradeRequest {
+            id,
+            ..TradeRequest::normal(order_side, price, quantity)
+        }
+    }
+
+    #[test]
+    fn event_sequence_produces_exact_updates() {
+        let mut state_machine = MatchingStateMachine::new(OrderbookConfig::default());
+        let sell_order_id = Uuid::new_v4();
+        let buy_order_id = Uuid::new_v4();
+
+        let events = vec![
+            MatchingEvent::Time(1_000),
+            MatchingEvent::OrderRequest(Box::new(OrderRequest::Trade(trade_request(
+                sell_order_id,
+                OrderSide::Sell,
+                10,
+                2,
+            )))),
+            MatchingEvent::Time(1_500),
+            MatchingEvent::OrderRequest(Box::new(OrderRequest::Trade(trade_request(
+                buy_order_id,
+                OrderSide::Buy,
+                10,
+                1,
+            )))),
+            MatchingEvent::OrderRequest(Box::new(OrderRequest::Cancel(
+                CancelRequestType::User,
+                sell_order_id,
+            ))),
+        ];
+
+        let updates: Vec<Vec<MarketDataUpdate>> = events
+            .into_iter()
+            .map(|event| state_machine.apply(event))
+            .collect();
+
+        let expected_trade = Trade {
+            bid: TradeInfo {
+                order_id: buy_order_id,
+                price: 10,
+                quantity: 1,
+            },
+            ask: TradeInfo {
+                order_id: sell_order_id,
+                price: 10,
+                quantity: 1,
+            },
+            aggressor_side: OrderSide::Buy,
+            resting_order_audit: TradeAudit {
+                queue_position: 0,
+                resting_duration_ms: 500,
+            },
+            effective_spread: None,
+        };
+        let expected_cancellation = CancelledOrder {
+            cancel_request_type: CancelRequestType::User,
+            order: Order {
+                type_: OrderType::Normal,
+                id: sell_order_id,
+                side: OrderSide::Sell,
+                price: 10,
+                initial_quantity: 2,
+                remaining_quantity: 1,
+                minimum_quantity: 0,
+                virtual_remaining_quantity: 1,
+                inserted_at: 1_000,
+                sequence: 1,
+                min_fill_ratio: None,
+                account_id: None,
+            },
+        };
+
+        assert_eq!(
+            updates,
+            vec![
+                vec![],
+                vec![],
+                vec![],
+                vec![MarketDataUpdate::Trade(expected_trade)],
+                vec![MarketDataUpdate::Cancellation(expected_cancellation)],
+            ]
+        );
+    }
+
+    enum LoggedRequest {
+        Trade(TradeRequest),
+        Cancel(Uuid),
+    }
+
+    impl LoggedRequest {
+        fn to_order_request(&self) -> OrderRequest {
+            match self {
+                LoggedRequest::Trade(trade_request) => OrderRequest::Trade(trade_request.clone()),
+                LoggedRequest::Cancel(order_id) => {
+                    OrderRequest::Cancel(CancelRequestType::User, *order_id)
+                }
+            }
+        }
+    }
+
+    fn apply_logged(
+        state_machine: &mut MatchingStateMachine,
+        timestamp_millis: i64,
+        logged_request: &LoggedRequest,
+    ) -> Vec<MarketDataUpdate> {
+        state_machine.apply(MatchingEvent::Time(timestamp_millis));
+        state_machine.apply(MatchingEvent::OrderRequest(Box::new(
+            logged_request.to_order_request(),
+        )))
+    }
+
+    #[test]
+    fn replayed_request_log_reproduces_live_updates() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut live_state_machine = MatchingStateMachine::new(OrderbookConfig::default());
+        let mut request_log = vec![];
+        let mut live_updates = vec![];
+        let mut order_ids = vec![];
+
+        for timestamp_millis in 0..500 {
+            let logged_request = if !order_ids.is_empty() && rng.random_bool(0.2) {
+                let index = rng.random_range(0..order_ids.len() as u64) as usize;
+                LoggedRequest::Cancel(order_ids[index])
+            } else {
+                let order_side = if rng.random_bool(0.5) {
+                    OrderSide::Buy
+                } else {
+                    OrderSide::Sell
+                };
+                let request = trade_request(
+                    Uuid::new_v4(),
+                    order_side,
+                    rng.random_range(1..10),
+                    rng.random_range(1..10),
+                );
+                order_ids.push(request.id);
+                LoggedRequest::Trade(request)
+            };
+
+            live_updates.extend(apply_logged(
+                &mut live_state_machine,
+                timestamp_millis,
+                &logged_request,
+            ));
+            request_log.push((timestamp_millis, logged_request));
+        }
+
+        let mut replay_state_machine = MatchingStateMachine::new(OrderbookConfig::default());
+        let replayed_updates: Vec<MarketDataUpdate> = request_log
+            .iter()
+            .flat_map(|(timestamp_millis, logged_request)| {
+                apply_logged(&mut replay_state_machine, *timestamp_millis, logged_request)
+            })
+            .collect();
+
+        assert!(!live_updates.is_empty());
+        assert_eq!(replayed_updates, live_updates);
+        assert_eq!(
+            borsh::to_vec(&replayed_updates).unwrap(),
+            borsh::to_vec(&live_updates).unwrap()
+        );
+    }
+}
@@ -0,0 +1 @@
+pub mod parquet_exporter;
@@ -0,0 +1,268 @@
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use arrow::{
+    array::{ArrayRef, Int64Array, StringArray, UInt64Array},
+    datatypes::{DataType, Field, Schema},
+    record_batch::RecordBatch,
+};
+use chrono::Utc;
+use crossbeam::channel::{Receiver, RecvTimeoutError};
+use parquet::arrow::ArrowWriter;
+use uuid::Uuid;
+
+use crate::orderbook::{MarketDataUpdate, OrderSide, Trade};
+
+pub const DEFAULT_EXPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// One row of an exported trade file
+#[derive(Clone, PartialEq, Debug)]
+pub struct TradeRecord {
+    /// Millisecond timestamp of when the exporter received the trade
+    pub timestamp: i64,
+    pub aggressor_side: OrderSide,
+    pub price: i64,
+    pub quantity: u64,
+    pub bid_order_id: Uuid,
+    pub ask_order_id: Uuid,
+}
+
+impl TradeRecord {
+    fn new(trade: &Trade, timestamp: i64) -> Self {
+        Self {
+            timestamp,
+            aggressor_side: trade.aggressor_side(),
+            price: trade.price(),
+            quantity: trade.quantity(),
+            bid_order_id: trade.bid_order_id(),
+            ask_order_id: trade.ask_order_id(),
+        }
+    }
+}
+
+/// Writes the trades from a market data channel to a Parquet file
+/// in output_dir every export_interval, for analysis in DataFrames
+///
+/// Give it a channel of its own, as receivers on a shared channel
+/// each get only some of the updates
+pub struct ParquetExporter {
+    market_data_receiver: Receiver<MarketDataUpdate>,
+    output_dir: PathBuf,
+    export_interval: Duration,
+    pending_trades: Vec<TradeRecord>,
+    files_written: usize,
+}
+
+impl ParquetExporter {
+    pub fn new(
+        market_data_receiver: Receiver<MarketDataUpdate>,
+        output_dir: impl Into<PathBuf>,
+        export_interval: Duration,
+    ) -> Self {
+        Self {
+            market_data_receiver,
+            output_dir: output_dir.into(),
+            export_interval,
+            pending_trades: vec![],
+            files_written: 0,
+        }
+    }
+
+    /// Exports until every sender is gone, writing out any trades still pending
+    pub fn run(&mut self) {
+        let mut next_export_at = Instant::now() + self.export_interval;
+        loop {
+            let timeout = next_export_at.saturating_duration_since(Instant::now());
+            match self.market_data_receiver.recv_timeout(timeout) {
+                Ok(market_data_update) => {
+                    self.record(market_data_update, Utc::now().timestamp_millis());
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.export_logging_errors();
+                    return;
+                }
+            }
+
+            if Instant::now() >= next_export_at {
+                self.export_logging_errors();
+                next_export_at = Instant::now() + self.export_interval;
+            }
+        }
+    }
+
+    /// Keeps the trades in an update, including those in a batch
+    fn record(&mut self, market_data_update: MarketDataUpdate, timestamp: i64) {
+        match market_data_update {
+            MarketDataUpdate::Trade(trade) => {
+                self.pending_trades
+                    .push(TradeRecord::new(&trade, timestamp));
+            }
+            MarketDataUpdate::Batch(market_data_updates) => {
+                for market_data_update in market_data_updates {
+                    self.record(market_data_update, timestamp);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn export_logging_errors(&mut self) {
+        if let Err(error) = self.export() {
+            eprintln!("Failed to export trades to Parquet: {}", error);
+        }
+    }
+
+    /// Writes the pending trades to a new file, returning its path,
+    /// None if there were no trades to write
+    ///
+    /// Trades are kept for the next export if the write fails
+    fn export(&mut self) -> Result<Option<PathBuf>> {
+        if self.pending_trades.is_empty() {
+            return Ok(None);
+        }
+
+        let path = self.output_dir.join(format!(
+            "trades_{}_{}.parquet",
+            Utc::now().timestamp_millis(),
+            self.files_written
+        ));
+        write_trades(&path, &self.pending_trades)?;
+        self.pending_trades.clear();
+        self.files_written += 1;
+        Ok(Some(path))
+    }
+}
+
+/// Columns of an exported trade file
+pub fn trade_schema() -> Schema {
+    Schema::new(vec![
+        Field::new("timestamp", DataType::Int64, false),
+        Field::new("side", DataType::Utf8, false),
+        Field::new("price", DataType::Int64, false),
+        Field::new("quantity", DataType::UInt64, false),
+        Field::new("bid_order_id", DataType::Utf8, false),
+        Field::new("ask_order_id", DataType::Utf8, false),
+    ])
+}
+
+/// Writes trades to a single Parquet file at path, side is the aggressor's
+pub fn write_trades(path: &Path, trades: &[TradeRecord]) -> Result<()> {
+    let schema = Arc::new(trade_schema());
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(Int64Array::from_iter_values(
+            trades.iter().map(|trade| trade.timestamp),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            trades
+                .iter()
+                .map(|trade| format!("{:?}", trade.aggressor_side)),
+        )),
+        Arc::new(Int64Array::from_iter_values(
+            trades.iter().map(|trade| trade.price),
+        )),
+        Arc::new(UInt64Array::from_iter_values(
+            trades.iter().map(|trade| trade.quantity),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            trades.iter().map(|trade| trade.bid_order_id.to_string()),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            trades.iter().map(|trade| trade.ask_order_id.to_string()),
+        )),
+    ];
+    let record_batch = RecordBatch::try_new(schema.clone(), columns)?;
+
+    let mut writer = ArrowWriter::try_new(File::create(path)?, schema, None)?;
+    writer.write(&record_batch)?;
+    writer.close()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crossbeam::channel;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    use crate::{
+        orderbook::orderbook::Orderbook,
+        web_server::{OrderRequest, TradeRequest},
+    };
+
+    use super::*;
+
+    #[test]
+    fn trades_exported_to_readable_parquet_file() {
+        let output_dir = std::env::temp_dir().join(format!("parquet_export_{}", Uuid::new_v4()));
+        fs::create_dir_all(&output_dir).unwrap();
+
+        let (market_data_sender, market_data_receiver) = channel::unbounded();
+        let mut orderbook = Orderbook::new(Some(market_data_sender));
+        for _ in 0..3 {
+            orderbook
+                .place_trade_request(OrderRequest::Trade(TradeRequest::normal(
+                    OrderSide::Sell,
+                    10,
+                    1,
+                )))
+                .unwrap();
+        }
+        orderbook
+            .place_trade_request(OrderRequest::Trade(TradeRequest::normal(
+                OrderSide::Buy,
+                10,
+                3,
+            )))
+            .unwrap();
+        drop(orderbook);
+
+        let mut exporter =
+            ParquetExporter::new(market_data_receiver, &output_dir, DEFAULT_EXPORT_INTERVAL);
+        exporter.run();
+
+        let paths: Vec<PathBuf> = fs::read_dir(&output_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(paths.len(), 1);
+
+        let builder =
+            ParquetRecordBatchReaderBuilder::try_new(File::open(&paths[0]).unwrap()).unwrap();
+        assert_eq!(builder.schema().fields(), trade_schema().fields());
+        let row_count: usize = builder
+            .build()
+            .unwrap()
+            .map(|record_batch| record_batch.unwrap().num_rows())
+            .sum();
+        assert_eq!(row_count, 3);
+
+        fs::remove_dir_all(&output_dir).unwrap();
+    }
+
+    #[test]
+    fn nothing_exported_without_trades() {
+        let (_market_data_sender, market_data_receiver) = channel::unbounded();
+        let mut exporter = ParquetExporter::new(
+            market_data_receiver,
+            std::env::temp_dir(),
+            DEFAULT_EXPORT_INTERVAL,
+        );
+
+        exporter.record(
+            MarketDataUpdate::OrderRejected {
+                id: Uuid::new_v4(),
+                reason: "rejected".to_string(),
+            },
+            0,
+        );
+
+        assert!(exporter.export().unwrap().is_none());
+    }
+}
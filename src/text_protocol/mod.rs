@@ -0,0 +1,125 @@
+use anyhow::{anyhow, bail, Result};
+use uuid::Uuid;
+
+use crate::{
+    orderbook::{OrderSide, OrderType},
+    web_server::{CancelRequestType, OrderRequest, TradeRequest},
+};
+
+pub mod text_protocol_server;
+
+/// Parses a single line of the text protocol into an order request
+///
+/// Supported commands:
+/// - `BUY <quantity> @ <price> [MIN <minimum_quantity>]`
+/// - `SELL <quantity> @ <price> [MIN <minimum_quantity>]`
+/// - `CANCEL <order_id>`
+///
+/// New orders are given a fresh id and are always normal,
+/// non expiring orders. Meant for scripting and debugging,
+/// clients should use the HTTP endpoints
+pub fn parse_command(line: &str) -> Result<OrderRequest> {
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+
+    match tokens.as_slice() {
+        ["CANCEL", order_id] => {
+            let order_id = Uuid::parse_str(order_id).map_err(|_| anyhow!("Invalid order id"))?;
+            Ok(OrderRequest::Cancel(CancelRequestType::User, order_id))
+        }
+        [side @ ("BUY" | "SELL"), quantity, "@", price, minimum_quantity @ ..] => {
+            let order_side = match *side {
+                "BUY" => OrderSide::Buy,
+                _ => OrderSide::Sell,
+            };
+            let quantity = quantity.parse().map_err(|_| anyhow!("Invalid quantity"))?;
+            let price = price.parse().map_err(|_| anyhow!("Invalid price"))?;
+            let minimum_quantity = match minimum_quantity {
+                [] => 0,
+                ["MIN", minimum_quantity] => minimum_quantity
+                    .parse()
+                    .map_err(|_| anyhow!("Invalid minimum quantity"))?,
+                _ => bail!("Unexpected arguments after price"),
+            };
+
+            if minimum_quantity > quantity {
+                bail!("Minimum quantity > quantity")
+            }
+
+            Ok(OrderRequest::Trade(TradeRequest {
+                id: Uuid::new_v4(),
+                order_type: OrderType::Normal,
+                order_side,
+                price,
+                quantity,
+                minimum_quantity,
+                expiration_date: None,
+                sent_at: None,
+                min_fill_ratio: None,
+                account_id: None,
+            }))
+        }
+        _ => bail!("Unknown command"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_trade(line: &str) -> TradeRequest {
+        match parse_command(line).unwrap() {
+            OrderRequest::Trade(trade_request) => trade_request,
+            order_request => panic!("Expected trade, got {:?}", order_request),
+        }
+    }
+
+    #[test]
+    fn parses_buy_with_minimum_quantity() {
+        let trade_request = parse_trade("BUY 100 @ 50 MIN 10");
+
+        assert_eq!(trade_request.order_side, OrderSide::Buy);
+        assert_eq!(trade_request.order_type, OrderType::Normal);
+        assert_eq!(trade_request.quantity, 100);
+        assert_eq!(trade_request.price, 50);
+        assert_eq!(trade_request.minimum_quantity, 10);
+    }
+
+    #[test]
+    fn parses_sell_without_minimum_quantity() {
+        let trade_request = parse_trade("SELL 5 @ -3");
+
+        assert_eq!(trade_request.order_side, OrderSide::Sell);
+        assert_eq!(trade_request.quantity, 5);
+        assert_eq!(trade_request.price, -3);
+        assert_eq!(trade_request.minimum_quantity, 0);
+    }
+
+    #[test]
+    fn parses_cancel() {
+        let order_id = Uuid::new_v4();
+
+        assert!(matches!(
+            parse_command(&format!("CANCEL {}", order_id)).unwrap(),
+            OrderRequest::Cancel(CancelRequestType::User, cancelled_id) if cancelled_id == order_id
+        ));
+    }
+
+    #[test]
+    fn rejects_malformed_commands() {
+        for line in [
+            "",
+            "HOLD 1 @ 1",
+            "BUY 1 1",
+            "BUY -1 @ 1",
+            "BUY 1 @ 1 MIN",
+            "BUY 1 @ 1 MIN 2",
+            "CANCEL not-a-uuid",
+        ] {
+            assert!(
+                parse_command(line).is_err(),
+                "{:?} should be rejected",
+                line
+            );
+        }
+    }
+}
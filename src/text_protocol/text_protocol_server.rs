@@ -0,0 +1,107 @@
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    thread,
+};
+
+use crossbeam::channel::Sender;
+
+use crate::{metrics::REQUESTS_COUNTER, web_server::OrderRequest};
+
+use super::parse_command;
+
+/// Accepts text protocol connections, forwarding each command to the order engine
+///
+/// Every line gets a single line response, either `ACK <order_id>`
+/// once the request is queued or `REJECT <reason>`
+pub struct TextProtocolServer {
+    order_engine_sender: Sender<OrderRequest>,
+}
+
+impl TextProtocolServer {
+    pub fn new(order_engine_sender: Sender<OrderRequest>) -> Self {
+        Self {
+            order_engine_sender,
+        }
+    }
+
+    pub fn run(&self, address: impl ToSocketAddrs) -> io::Result<()> {
+        let listener = TcpListener::bind(address)?;
+
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let order_engine_sender = self.order_engine_sender.clone();
+                    thread::spawn(move || {
+                        if let Err(error) = handle_connection(stream, &order_engine_sender) {
+                            eprintln!("Text protocol connection failed: {}", error);
+                        }
+                    });
+                }
+                Err(error) => eprintln!("Failed to accept text protocol connection: {}", error),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn handle_connection(
+    stream: TcpStream,
+    order_engine_sender: &Sender<OrderRequest>,
+) -> io::Result<()> {
+    let reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        writeln!(writer, "{}", respond(&line, order_engine_sender))?;
+    }
+
+    Ok(())
+}
+
+fn respond(line: &str, order_engine_sender: &Sender<OrderRequest>) -> String {
+    REQUESTS_COUNTER.inc();
+
+    let order_request = match parse_command(line) {
+        Ok(order_request) => order_request,
+        Err(error) => return format!("REJECT {}", error),
+    };
+
+    let order_id = match &order_request {
+        OrderRequest::Trade(trade_request) => trade_request.id,
+        OrderRequest::Cancel(_, order_id) => *order_id,
+        _ => unreachable!("Text protocol only parses trades and cancels"),
+    };
+
+    match order_engine_sender.send(order_request) {
+        Ok(_) => format!("ACK {}", order_id),
+        Err(_) => "REJECT Order engine unavailable".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossbeam::channel;
+
+    use super::*;
+
+    #[test]
+    fn acks_queued_order_and_rejects_invalid_line() {
+        let (tx, rx) = channel::unbounded();
+
+        let response = respond("BUY 1 @ 1", &tx);
+        let queued_id = match rx.try_recv().unwrap() {
+            OrderRequest::Trade(trade_request) => trade_request.id,
+            order_request => panic!("Expected trade, got {:?}", order_request),
+        };
+        assert_eq!(response, format!("ACK {}", queued_id));
+
+        assert_eq!(respond("BUY 1", &tx), "REJECT Unknown command");
+        assert!(rx.try_recv().is_err());
+    }
+}
@@ -1,23 +1,164 @@
-use actix_web::{get, post, web, HttpResponse, Responder};
+use actix_web::{
+    error::{InternalError, JsonPayloadError},
+    get, post, web, HttpRequest, HttpResponse, Responder,
+};
 
+use chrono::Utc;
 use prometheus::{Encoder, TextEncoder};
+use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
 use uuid::Uuid;
 
 use crate::{
     expiration_handler::{ExpirationOrderRequest, InsertExpirationRequest},
-    metrics::{REGISTRY, REQUESTS_COUNTER},
-    web_server::{AppState, OrderRequest, TradeRequest},
+    metrics::{ORDER_BACKLOG, REGISTRY, REQUESTS_COUNTER},
+    web_server::{AppState, CancelLevelRequest, OrderRequest, TradeRequest},
 };
 
+const DEFAULT_RECENT_TRADES_LIMIT: usize = 100;
+const DEFAULT_RECENT_REJECTIONS_LIMIT: usize = 100;
+const MAX_BOOK_HISTOGRAM_LEVELS: usize = 1000;
+const DEFAULT_DEPTH_LEVELS: usize = 10;
+const MAX_DEPTH_LEVELS: usize = 1000;
+const ADMIN_KEY_HEADER: &str = "x-admin-key";
+pub const MAX_ORDER_REQUEST_BYTES: usize = 4096;
+
+#[derive(Deserialize)]
+pub struct RecentTradesQuery {
+    limit: Option<usize>,
+}
+
+#[derive(Deserialize)]
+pub struct BookHistogramQuery {
+    levels: Option<usize>,
+}
+
+#[derive(Deserialize)]
+pub struct ModifyOrderQuery {
+    safe_reprice: Option<bool>,
+}
+
+#[derive(Deserialize)]
+pub struct DepthQuery {
+    levels: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BacklogResponse {
+    backlog: usize,
+}
+
+#[derive(Serialize)]
+struct JsonErrorResponse {
+    error: &'static str,
+    detail: String,
+}
+
+/// Returns a structured 400 for JSON bodies that can't be parsed
+///
+/// Syntactically invalid JSON is reported separately from valid
+/// JSON that doesn't describe a valid request
+pub fn json_error_handler(err: JsonPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    let error = match &err {
+        JsonPayloadError::Deserialize(deserialize_error) if deserialize_error.is_data() => {
+            "invalid request"
+        }
+        JsonPayloadError::Deserialize(_) => "invalid json",
+        JsonPayloadError::ContentType => "invalid content type",
+        _ => return err.into(),
+    };
+
+    let response = HttpResponse::BadRequest().json(JsonErrorResponse {
+        error,
+        detail: err.to_string(),
+    });
+    InternalError::from_response(err, response).into()
+}
+
+/// Whether the request carries the configured admin key,
+/// always false when no admin key is configured
+fn has_admin_key(request: &HttpRequest, state: &AppState) -> bool {
+    let admin_key = request
+        .headers()
+        .get(ADMIN_KEY_HEADER)
+        .and_then(|admin_key| admin_key.to_str().ok());
+    matches!(
+        (&state.admin_key, admin_key),
+        (Some(expected_admin_key), Some(admin_key)) if expected_admin_key == admin_key
+    )
+}
+
+/// JSON body config for the server, larger bodies are rejected
+/// with a 413 and malformed ones with a structured 400
+pub fn json_config() -> web::JsonConfig {
+    web::JsonConfig::default()
+        .limit(MAX_ORDER_REQUEST_BYTES)
+        .error_handler(json_error_handler)
+}
+
+/// Modifies a resting order, with safe_reprice=true the modify
+/// is rejected instead of trading if the new price would cross
 #[post("/modify_order")]
 async fn modify_order_endpoint(
     order_request: web::Json<TradeRequest>,
+    query: web::Query<ModifyOrderQuery>,
     state: web::Data<AppState>,
 ) -> impl Responder {
     REQUESTS_COUNTER.inc();
+
+    let trade_request = order_request.into_inner();
+    if trade_request.is_stale(state.max_order_age, Utc::now()) {
+        return HttpResponse::BadRequest().finish();
+    }
+
+    let modify_request = match query.safe_reprice.unwrap_or(false) {
+        true => OrderRequest::SafeReprice(trade_request),
+        false => OrderRequest::Modify(trade_request),
+    };
+
+    match state.order_engine_sender.send(modify_request) {
+        Ok(_) => HttpResponse::Ok().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[post("/replace_order/{order_id}")]
+async fn replace_order_endpoint(
+    order_id: web::Path<Uuid>,
+    order_request: web::Json<TradeRequest>,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    REQUESTS_COUNTER.inc();
+
+    let trade_request = order_request.into_inner();
+    if trade_request.is_stale(state.max_order_age, Utc::now()) {
+        return HttpResponse::BadRequest().finish();
+    }
+
     match state
         .order_engine_sender
-        .send(OrderRequest::Modify(order_request.into_inner()))
+        .send(OrderRequest::Replace(order_id.into_inner(), trade_request))
+    {
+        Ok(_) => HttpResponse::Ok().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[post("/upsert_order")]
+async fn upsert_order_endpoint(
+    order_request: web::Json<TradeRequest>,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    REQUESTS_COUNTER.inc();
+
+    let trade_request = order_request.into_inner();
+    if trade_request.is_stale(state.max_order_age, Utc::now()) {
+        return HttpResponse::BadRequest().finish();
+    }
+
+    match state
+        .order_engine_sender
+        .send(OrderRequest::Upsert(trade_request))
     {
         Ok(_) => HttpResponse::Ok().finish(),
         Err(_) => HttpResponse::InternalServerError().finish(),
@@ -31,8 +172,8 @@ async fn cancel_order_endpoint(
 ) -> impl Responder {
     REQUESTS_COUNTER.inc();
 
-    match state.order_engine_sender.send(OrderRequest::Cancel(
-        crate::web_server::CancelRequestType::External,
+    match state.cancel_request_sender.send(OrderRequest::Cancel(
+        crate::web_server::CancelRequestType::User,
         order_id.into_inner(),
     )) {
         Ok(_) => HttpResponse::Ok().finish(),
@@ -40,6 +181,65 @@ async fn cancel_order_endpoint(
     }
 }
 
+/// Cancels all given orders in one pass on the order engine
+///
+/// Responds with whether each order was found
+#[post("/cancel_orders")]
+async fn cancel_orders_endpoint(
+    order_ids: web::Json<Vec<Uuid>>,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    REQUESTS_COUNTER.inc();
+
+    let (reply_sender, reply_receiver) = oneshot::channel();
+
+    if state
+        .cancel_request_sender
+        .send(OrderRequest::CancelMany(
+            order_ids.into_inner(),
+            reply_sender,
+        ))
+        .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    match reply_receiver.await {
+        Ok(cancel_results) => HttpResponse::Ok().json(cancel_results),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Cancels every order resting at a price level on the order engine,
+/// only those of the request's account if given
+///
+/// Responds with the ids of the cancelled orders
+#[post("/cancel_level")]
+async fn cancel_level_endpoint(
+    cancel_level_request: web::Json<CancelLevelRequest>,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    REQUESTS_COUNTER.inc();
+
+    let (reply_sender, reply_receiver) = oneshot::channel();
+
+    if state
+        .cancel_request_sender
+        .send(OrderRequest::CancelLevel(
+            cancel_level_request.into_inner(),
+            reply_sender,
+        ))
+        .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    match reply_receiver.await {
+        Ok(cancelled_order_ids) => HttpResponse::Ok().json(cancelled_order_ids),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
 #[post("/create_order")]
 async fn create_order_endpoint(
     order_request: web::Json<TradeRequest>,
@@ -48,8 +248,12 @@ async fn create_order_endpoint(
     REQUESTS_COUNTER.inc();
 
     let trade_request = order_request.into_inner();
+    if trade_request.is_stale(state.max_order_age, Utc::now()) {
+        return HttpResponse::BadRequest().finish();
+    }
+
     let trade_request_id = trade_request.id;
-    let expiration_date = trade_request.expiration_date;
+    let expiration_timestamp = trade_request.expiration_timestamp_millis();
 
     if state
         .order_engine_sender
@@ -59,9 +263,9 @@ async fn create_order_endpoint(
         return HttpResponse::InternalServerError().finish();
     }
 
-    if let Some(expiration_date) = expiration_date {
+    if let Some(timestamp) = expiration_timestamp {
         let expiration_request = InsertExpirationRequest {
-            timestamp: expiration_date.and_utc().timestamp(),
+            timestamp,
             order_id: trade_request_id,
         };
 
@@ -79,6 +283,33 @@ async fn create_order_endpoint(
     HttpResponse::Ok().finish()
 }
 
+/// Creates two linked orders, a fill on either cancels the other
+///
+/// Expiration dates aren't supported on OCO orders
+#[post("/create_oco_order")]
+async fn create_oco_order_endpoint(
+    order_requests: web::Json<(TradeRequest, TradeRequest)>,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    REQUESTS_COUNTER.inc();
+
+    let (first_trade_request, second_trade_request) = order_requests.into_inner();
+    let now = Utc::now();
+    if first_trade_request.is_stale(state.max_order_age, now)
+        || second_trade_request.is_stale(state.max_order_age, now)
+    {
+        return HttpResponse::BadRequest().finish();
+    }
+
+    match state
+        .order_engine_sender
+        .send(OrderRequest::Oco(first_trade_request, second_trade_request))
+    {
+        Ok(_) => HttpResponse::Ok().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
 #[get("/metrics")]
 async fn metrics_endpoint() -> impl Responder {
     let encoder = TextEncoder::new();
@@ -105,3 +336,554 @@ async fn cancel_order_expiration_endpoint(
         Err(_) => HttpResponse::InternalServerError().finish(),
     }
 }
+
+/// Expires the order now, through the same path as a timed expiry,
+/// so its cancellation is labelled as an expiry rather than a user cancel
+///
+/// Requires the configured admin key in the x-admin-key header
+#[post("/expire_order/{order_id}")]
+async fn expire_order_endpoint(
+    request: HttpRequest,
+    order_id: web::Path<Uuid>,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    REQUESTS_COUNTER.inc();
+
+    if !has_admin_key(&request, &state) {
+        return HttpResponse::Unauthorized().finish();
+    }
+
+    match state
+        .order_expiration_sender
+        .send(ExpirationOrderRequest::ForceExpireRequest(
+            order_id.into_inner(),
+        )) {
+        Ok(_) => HttpResponse::Ok().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[get("/trades")]
+async fn recent_trades_endpoint(
+    query: web::Query<RecentTradesQuery>,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    REQUESTS_COUNTER.inc();
+
+    let limit = query.limit.unwrap_or(DEFAULT_RECENT_TRADES_LIMIT);
+    let (reply_sender, reply_receiver) = oneshot::channel();
+
+    if state
+        .order_engine_sender
+        .send(OrderRequest::RecentTrades(limit, reply_sender))
+        .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    match reply_receiver.await {
+        Ok(trades) => HttpResponse::Ok().json(trades),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Most recently rejected orders with their reasons, newest first
+#[get("/recent_rejections")]
+async fn recent_rejections_endpoint(
+    query: web::Query<RecentTradesQuery>,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    REQUESTS_COUNTER.inc();
+
+    let limit = query.limit.unwrap_or(DEFAULT_RECENT_REJECTIONS_LIMIT);
+    let (reply_sender, reply_receiver) = oneshot::channel();
+
+    if state
+        .order_engine_sender
+        .send(OrderRequest::RecentRejections(limit, reply_sender))
+        .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    match reply_receiver.await {
+        Ok(rejected_orders) => HttpResponse::Ok().json(rejected_orders),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[get("/quote")]
+async fn quote_endpoint(state: web::Data<AppState>) -> impl Responder {
+    REQUESTS_COUNTER.inc();
+
+    let (reply_sender, reply_receiver) = oneshot::channel();
+
+    if state
+        .order_engine_sender
+        .send(OrderRequest::Quote(reply_sender))
+        .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    match reply_receiver.await {
+        Ok(quote) => HttpResponse::Ok().json(quote),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Configuration the orderbook is matching with, for working
+/// out why orders are being rejected
+#[get("/config")]
+async fn config_endpoint(state: web::Data<AppState>) -> impl Responder {
+    REQUESTS_COUNTER.inc();
+
+    let (reply_sender, reply_receiver) = oneshot::channel();
+
+    if state
+        .order_engine_sender
+        .send(OrderRequest::Config(reply_sender))
+        .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    match reply_receiver.await {
+        Ok(config) => HttpResponse::Ok().json(config),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Aggregate quantity and order count at every occupied price
+///
+/// Up to levels levels per side, zero levels gives an empty
+/// histogram and more than MAX_BOOK_HISTOGRAM_LEVELS is clamped
+#[get("/book_histogram")]
+async fn book_histogram_endpoint(
+    query: web::Query<BookHistogramQuery>,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    REQUESTS_COUNTER.inc();
+
+    let levels = query
+        .levels
+        .unwrap_or(MAX_BOOK_HISTOGRAM_LEVELS)
+        .min(MAX_BOOK_HISTOGRAM_LEVELS);
+    let (reply_sender, reply_receiver) = oneshot::channel();
+
+    if state
+        .order_engine_sender
+        .send(OrderRequest::BookHistogram(levels, reply_sender))
+        .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    match reply_receiver.await {
+        Ok(book_levels) => HttpResponse::Ok().json(book_levels),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Current status of an order
+///
+/// Filled and cancelled orders have left the book, so they're
+/// reported NotFound just like unknown ids
+#[get("/order/{order_id}")]
+async fn order_status_endpoint(
+    order_id: web::Path<Uuid>,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    REQUESTS_COUNTER.inc();
+
+    let (reply_sender, reply_receiver) = oneshot::channel();
+
+    if state
+        .order_engine_sender
+        .send(OrderRequest::Query(order_id.into_inner(), reply_sender))
+        .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    match reply_receiver.await {
+        Ok(order_status_report) => HttpResponse::Ok().json(order_status_report),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Total quantity and order count at each price, bids
+/// descending and asks ascending
+///
+/// Up to levels levels per side, defaulting to DEFAULT_DEPTH_LEVELS
+/// and clamped to MAX_DEPTH_LEVELS
+#[get("/depth")]
+async fn depth_endpoint(
+    query: web::Query<DepthQuery>,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    REQUESTS_COUNTER.inc();
+
+    let levels = query
+        .levels
+        .unwrap_or(DEFAULT_DEPTH_LEVELS)
+        .min(MAX_DEPTH_LEVELS);
+    let (reply_sender, reply_receiver) = oneshot::channel();
+
+    if state
+        .order_engine_sender
+        .send(OrderRequest::Snapshot(levels, reply_sender))
+        .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    match reply_receiver.await {
+        Ok(depth_snapshot) => HttpResponse::Ok().json(depth_snapshot),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Every resting order, for debugging
+///
+/// Requires the configured admin key in the x-admin-key header
+#[get("/admin/book")]
+async fn admin_book_endpoint(request: HttpRequest, state: web::Data<AppState>) -> impl Responder {
+    REQUESTS_COUNTER.inc();
+
+    if !has_admin_key(&request, &state) {
+        return HttpResponse::Unauthorized().finish();
+    }
+
+    let (reply_sender, reply_receiver) = oneshot::channel();
+
+    if state
+        .order_engine_sender
+        .send(OrderRequest::Book(reply_sender))
+        .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    match reply_receiver.await {
+        Ok(orders) => HttpResponse::Ok().json(orders),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Number of order requests queued for the order engine
+#[get("/backlog")]
+async fn backlog_endpoint(state: web::Data<AppState>) -> impl Responder {
+    let backlog = state.order_engine_sender.len();
+    ORDER_BACKLOG.set(backlog as i64);
+
+    HttpResponse::Ok().json(BacklogResponse { backlog })
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::header::ContentType, http::StatusCode, test, web::Bytes, App};
+    use chrono::Duration;
+    use crossbeam::channel;
+
+    use crate::{
+        orderbook::{orderbook::Orderbook, Order, OrderSide, OrderType},
+        web_server::{CancelOrderResult, CancelRequestType},
+    };
+
+    use super::*;
+
+    async fn post_create_order(payload: &'static str) -> (StatusCode, serde_json::Value) {
+        let (status, body) = call_create_order(payload.to_string()).await;
+
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    async fn call_create_order(payload: String) -> (StatusCode, Bytes) {
+        let (order_engine_sender, _order_engine_receiver) = channel::unbounded();
+        let (order_expiration_sender, _order_expiration_receiver) = channel::unbounded();
+        let state = web::Data::new(AppState {
+            cancel_request_sender: order_engine_sender.clone(),
+            order_engine_sender,
+            order_expiration_sender,
+            max_order_age: Duration::seconds(5),
+            admin_key: None,
+        });
+
+        let app = test::init_service(
+            App::new()
+                .app_data(state)
+                .app_data(json_config())
+                .service(create_order_endpoint),
+        )
+        .await;
+
+        let request = test::TestRequest::post()
+            .uri("/create_order")
+            .insert_header(ContentType::json())
+            .set_payload(payload)
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        let status = response.status();
+
+        (status, test::read_body(response).await)
+    }
+
+    #[actix_web::test]
+    async fn malformed_json_returns_structured_error() {
+        let (status, body) = post_create_order("{ \"id\": ").await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "invalid json");
+        assert!(body["detail"].is_string());
+    }
+
+    #[actix_web::test]
+    async fn invalid_request_returns_structured_error() {
+        let (status, body) = post_create_order("{ \"id\": \"not a uuid\" }").await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "invalid request");
+    }
+
+    #[actix_web::test]
+    async fn oversized_request_rejected() {
+        let payload = format!("{{ \"id\": \"{}\" }}", "a".repeat(MAX_ORDER_REQUEST_BYTES));
+
+        let (status, _) = call_create_order(payload).await;
+
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[actix_web::test]
+    async fn backlog_reports_queued_requests() {
+        let (order_engine_sender, _order_engine_receiver) = channel::unbounded();
+        let (order_expiration_sender, _order_expiration_receiver) = channel::unbounded();
+        for _ in 0..3 {
+            order_engine_sender
+                .send(OrderRequest::Cancel(
+                    CancelRequestType::User,
+                    Uuid::new_v4(),
+                ))
+                .unwrap();
+        }
+        let state = web::Data::new(AppState {
+            cancel_request_sender: order_engine_sender.clone(),
+            order_engine_sender,
+            order_expiration_sender,
+            max_order_age: Duration::seconds(5),
+            admin_key: None,
+        });
+
+        let app = test::init_service(App::new().app_data(state).service(backlog_endpoint)).await;
+
+        let request = test::TestRequest::get().uri("/backlog").to_request();
+        let response: BacklogResponse = test::call_and_read_body_json(&app, request).await;
+
+        assert_eq!(response.backlog, 3);
+    }
+
+    /// Requests a book histogram, returning the levels the order engine was asked for
+    async fn requested_book_histogram_levels(uri: &str) -> usize {
+        let (order_engine_sender, order_engine_receiver) = channel::unbounded();
+        let (order_expiration_sender, _order_expiration_receiver) = channel::unbounded();
+        let state = web::Data::new(AppState {
+            cancel_request_sender: order_engine_sender.clone(),
+            order_engine_sender,
+            order_expiration_sender,
+            max_order_age: Duration::seconds(5),
+            admin_key: None,
+        });
+
+        let order_engine = std::thread::spawn(move || match order_engine_receiver.recv() {
+            Ok(OrderRequest::BookHistogram(levels, reply_sender)) => {
+                let _ = reply_sender.send(vec![]);
+                levels
+            }
+            order_request => panic!("Expected book histogram, got {:?}", order_request),
+        });
+
+        let app =
+            test::init_service(App::new().app_data(state).service(book_histogram_endpoint)).await;
+        let request = test::TestRequest::get().uri(uri).to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        order_engine.join().unwrap()
+    }
+
+    #[actix_web::test]
+    async fn book_histogram_levels_clamped() {
+        assert_eq!(
+            requested_book_histogram_levels("/book_histogram?levels=0").await,
+            0
+        );
+        assert_eq!(
+            requested_book_histogram_levels("/book_histogram?levels=5").await,
+            5
+        );
+        assert_eq!(
+            requested_book_histogram_levels("/book_histogram?levels=1000000").await,
+            MAX_BOOK_HISTOGRAM_LEVELS
+        );
+        assert_eq!(
+            requested_book_histogram_levels("/book_histogram").await,
+            MAX_BOOK_HISTOGRAM_LEVELS
+        );
+    }
+
+    #[actix_web::test]
+    async fn cancel_orders_reports_whether_each_order_was_found() {
+        let (order_engine_sender, order_engine_receiver) = channel::unbounded();
+        let (order_expiration_sender, _order_expiration_receiver) = channel::unbounded();
+        let state = web::Data::new(AppState {
+            cancel_request_sender: order_engine_sender.clone(),
+            order_engine_sender,
+            order_expiration_sender,
+            max_order_age: Duration::seconds(5),
+            admin_key: None,
+        });
+        let mut orderbook = Orderbook::default();
+        let resting_request = TradeRequest::normal(OrderSide::Buy, 9, 1);
+        let resting_order_id = resting_request.id;
+        orderbook
+            .place_trade_request(OrderRequest::Trade(resting_request))
+            .unwrap();
+        let unknown_order_id = Uuid::new_v4();
+
+        let order_engine = std::thread::spawn(move || {
+            let order_request = order_engine_receiver.recv().unwrap();
+            assert!(matches!(order_request, OrderRequest::CancelMany(..)));
+            orderbook.place_trade_request(order_request).unwrap();
+            orderbook
+        });
+
+        let app =
+            test::init_service(App::new().app_data(state).service(cancel_orders_endpoint)).await;
+        let request = test::TestRequest::post()
+            .uri("/cancel_orders")
+            .set_json(vec![resting_order_id, unknown_order_id])
+            .to_request();
+        let response: Vec<CancelOrderResult> = test::call_and_read_body_json(&app, request).await;
+
+        let orderbook = order_engine.join().unwrap();
+        assert_eq!(
+            response,
+            vec![
+                CancelOrderResult {
+                    order_id: resting_order_id,
+                    found: true,
+                },
+                CancelOrderResult {
+                    order_id: unknown_order_id,
+                    found: false,
+                },
+            ]
+        );
+        assert!(orderbook.snapshot().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn admin_book_requires_admin_key() {
+        let (order_engine_sender, order_engine_receiver) = channel::unbounded();
+        let (order_expiration_sender, _order_expiration_receiver) = channel::unbounded();
+        let state = web::Data::new(AppState {
+            cancel_request_sender: order_engine_sender.clone(),
+            order_engine_sender,
+            order_expiration_sender,
+            max_order_age: Duration::seconds(5),
+            admin_key: Some("secret".to_string()),
+        });
+
+        let app = test::init_service(App::new().app_data(state).service(admin_book_endpoint)).await;
+
+        for admin_key in [None, Some("wrong")] {
+            let mut request = test::TestRequest::get().uri("/admin/book");
+            if let Some(admin_key) = admin_key {
+                request = request.insert_header((ADMIN_KEY_HEADER, admin_key));
+            }
+            let response = test::call_service(&app, request.to_request()).await;
+
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+        assert!(order_engine_receiver.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn admin_book_returns_orders_with_admin_key() {
+        let (order_engine_sender, order_engine_receiver) = channel::unbounded();
+        let (order_expiration_sender, _order_expiration_receiver) = channel::unbounded();
+        let state = web::Data::new(AppState {
+            cancel_request_sender: order_engine_sender.clone(),
+            order_engine_sender,
+            order_expiration_sender,
+            max_order_age: Duration::seconds(5),
+            admin_key: Some("secret".to_string()),
+        });
+        let orders = vec![
+            Order::new(OrderType::Normal, OrderSide::Buy, 9, 1, 0),
+            Order::new(OrderType::Normal, OrderSide::Sell, 11, 2, 0),
+        ];
+
+        let book_orders = orders.clone();
+        let order_engine = std::thread::spawn(move || match order_engine_receiver.recv() {
+            Ok(OrderRequest::Book(reply_sender)) => {
+                let _ = reply_sender.send(book_orders);
+            }
+            order_request => panic!("Expected book request, got {:?}", order_request),
+        });
+
+        let app = test::init_service(App::new().app_data(state).service(admin_book_endpoint)).await;
+        let request = test::TestRequest::get()
+            .uri("/admin/book")
+            .insert_header((ADMIN_KEY_HEADER, "secret"))
+            .to_request();
+        let response: serde_json::Value = test::call_and_read_body_json(&app, request).await;
+
+        order_engine.join().unwrap();
+        assert_eq!(response, serde_json::to_value(&orders).unwrap());
+    }
+
+    #[actix_web::test]
+    async fn expire_order_requires_admin_key() {
+        let (order_engine_sender, _order_engine_receiver) = channel::unbounded();
+        let (order_expiration_sender, order_expiration_receiver) = channel::unbounded();
+        let state = web::Data::new(AppState {
+            cancel_request_sender: order_engine_sender.clone(),
+            order_engine_sender,
+            order_expiration_sender,
+            max_order_age: Duration::seconds(5),
+            admin_key: Some("secret".to_string()),
+        });
+
+        let app =
+            test::init_service(App::new().app_data(state).service(expire_order_endpoint)).await;
+        let order_id = Uuid::new_v4();
+        let uri = format!("/expire_order/{}", order_id);
+
+        for admin_key in [None, Some("wrong")] {
+            let mut request = test::TestRequest::post().uri(&uri);
+            if let Some(admin_key) = admin_key {
+                request = request.insert_header((ADMIN_KEY_HEADER, admin_key));
+            }
+            let response = test::call_service(&app, request.to_request()).await;
+
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+        assert!(order_expiration_receiver.is_empty());
+
+        let request = test::TestRequest::post()
+            .uri(&uri)
+            .insert_header((ADMIN_KEY_HEADER, "secret"))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        match order_expiration_receiver.try_recv() {
+            Ok(ExpirationOrderRequest::ForceExpireRequest(received_order_id)) => {
+                assert_eq!(received_order_id, order_id);
+            }
+            _ => panic!("Expected a force expire request"),
+        }
+    }
+}
@@ -1,12 +1,16 @@
 use anyhow::anyhow;
 use borsh::{BorshDeserialize, BorshSerialize};
-use chrono::NaiveDateTime;
-use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize};
+use tokio::sync::oneshot;
 use uuid::Uuid;
 
 use crate::{
     expiration_handler::ExpirationOrderRequest,
-    orderbook::{Order, OrderSide, OrderType},
+    orderbook::{
+        BookLevel, DepthSnapshot, EffectiveConfig, Order, OrderSide, OrderStatusReport, OrderType,
+        Quote, RejectedOrder, Trade,
+    },
 };
 
 pub mod endpoints;
@@ -14,20 +18,85 @@ pub mod endpoints;
 type Price = i64;
 type Quantity = u64;
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Debug)]
 pub enum OrderRequest {
     Trade(TradeRequest),
     Cancel(CancelRequestType, Uuid),
     Modify(TradeRequest),
+    /// Modifies the order only if its new price doesn't cross, so
+    /// a reprice never takes liquidity
+    SafeReprice(TradeRequest),
+    /// Cancels the order with the given id, replacing it with a new id
+    Replace(Uuid, TradeRequest),
+    /// Modifies the order if resting, otherwise creates it
+    Upsert(TradeRequest),
+    /// Two linked orders, a fill on either cancels the other
+    Oco(TradeRequest, TradeRequest),
+    /// Most recent trades, newest first, up to limit
+    #[serde(skip)]
+    RecentTrades(usize, oneshot::Sender<Vec<Trade>>),
+    /// Every resting order, in sequence order
+    #[serde(skip)]
+    Book(oneshot::Sender<Vec<Order>>),
+    /// Most recent rejections, newest first, up to limit
+    #[serde(skip)]
+    RecentRejections(usize, oneshot::Sender<Vec<RejectedOrder>>),
+    #[serde(skip)]
+    Quote(oneshot::Sender<Quote>),
+    /// Configuration the orderbook is currently matching with
+    #[serde(skip)]
+    Config(oneshot::Sender<EffectiveConfig>),
+    /// Status of a single order by id
+    #[serde(skip)]
+    Query(Uuid, oneshot::Sender<OrderStatusReport>),
+    /// Aggregated bid and ask levels, best first, up to levels per side
+    #[serde(skip)]
+    Snapshot(usize, oneshot::Sender<DepthSnapshot>),
+    /// Every occupied price level, best first, up to limit levels per side
+    #[serde(skip)]
+    BookHistogram(usize, oneshot::Sender<Vec<BookLevel>>),
+    /// Cancels all given orders in one pass, replying with whether each was found
+    #[serde(skip)]
+    CancelMany(Vec<Uuid>, oneshot::Sender<Vec<CancelOrderResult>>),
+    /// Cancels a price level, replying with the ids of the cancelled orders
+    #[serde(skip)]
+    CancelLevel(CancelLevelRequest, oneshot::Sender<Vec<Uuid>>),
 }
 
-#[derive(Deserialize, Serialize, BorshSerialize, BorshDeserialize, Clone, Debug)]
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+pub struct CancelOrderResult {
+    pub order_id: Uuid,
+    pub found: bool,
+}
+
+/// Every order resting at a price level, only
+/// those of account_id if given
+#[derive(Deserialize, Serialize, Debug)]
+pub struct CancelLevelRequest {
+    pub side: OrderSide,
+    pub price: Price,
+    #[serde(default)]
+    pub account_id: Option<Uuid>,
+}
+
+#[derive(Deserialize, Serialize, BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq)]
+/// Why an order was cancelled
 pub enum CancelRequestType {
-    Internal,
-    External,
+    /// Requested by the client
+    User,
+    /// The order reached its expiration date
+    Expiry,
+    /// Part of cancelling a whole level or every order matching a predicate
+    MassCancel,
+    /// Cancelled to be replaced by a modify or replace
+    Replace,
+    /// The other order of its one-cancels-other pair was filled
+    OcoSiblingFilled,
+    /// Would have matched an order of the same account
+    SelfTradePrevention,
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct TradeRequest {
     pub id: Uuid,
     pub order_type: OrderType,
@@ -35,17 +104,101 @@ pub struct TradeRequest {
     pub price: Price,
     pub quantity: Quantity,
     pub minimum_quantity: Quantity,
-    pub expiration_date: Option<NaiveDateTime>,
+    /// RFC 3339, e.g. `2025-01-01T12:00:00+02:00`, or naive and taken to be UTC
+    #[serde(default, deserialize_with = "deserialize_expiration_date")]
+    pub expiration_date: Option<DateTime<Utc>>,
+    pub sent_at: Option<NaiveDateTime>,
+    /// Only valid on kill orders, see Order::min_fill_ratio
+    #[serde(default)]
+    pub min_fill_ratio: Option<f64>,
+    /// Participant placing the order, used for self trade prevention
+    #[serde(default)]
+    pub account_id: Option<Uuid>,
+}
+
+impl TradeRequest {
+    /// Whether the client sent timestamp is older than max age
+    ///
+    /// Requests without a sent timestamp are never stale
+    pub fn is_stale(&self, max_age: Duration, now: DateTime<Utc>) -> bool {
+        match self.sent_at {
+            Some(sent_at) => now - sent_at.and_utc() > max_age,
+            None => false,
+        }
+    }
+
+    /// Unix timestamp in milliseconds at which the order expires, if any
+    pub fn expiration_timestamp_millis(&self) -> Option<i64> {
+        self.expiration_date
+            .map(|expiration_date| expiration_date.timestamp_millis())
+    }
+}
+
+/// Parses an RFC 3339 timestamp, or a naive one as UTC
+fn deserialize_expiration_date<'de, D>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let Some(expiration_date) = <Option<String> as Deserialize>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+
+    DateTime::parse_from_rfc3339(&expiration_date)
+        .map(|expiration_date| expiration_date.to_utc())
+        .or_else(|_| {
+            expiration_date
+                .parse::<NaiveDateTime>()
+                .map(|expiration_date| expiration_date.and_utc())
+        })
+        .map(Some)
+        .map_err(|_| D::Error::custom(format!("invalid expiration date: {}", expiration_date)))
+}
+
+#[cfg(test)]
+impl TradeRequest {
+    /// Normal order with a fresh id and every optional field unset,
+    /// override the rest with struct update syntax
+    pub fn normal(order_side: OrderSide, price: Price, quantity: Quantity) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            order_type: OrderType::Normal,
+            order_side,
+            price,
+            quantity,
+            minimum_quantity: 0,
+            expiration_date: None,
+            sent_at: None,
+            min_fill_ratio: None,
+            account_id: None,
+        }
+    }
 }
 
 impl TryFrom<TradeRequest> for Order {
     type Error = anyhow::Error;
 
     fn try_from(trade_request: TradeRequest) -> Result<Self, Self::Error> {
+        if trade_request.id.is_nil() {
+            return Err(anyhow!("Order id cannot be nil"));
+        }
+
+        if trade_request.quantity == 0 {
+            return Err(anyhow!("Quantity cannot be zero"));
+        }
+
         if trade_request.minimum_quantity > trade_request.quantity {
             return Err(anyhow!("Minimum quantity > quantity"));
         }
 
+        if let Some(min_fill_ratio) = trade_request.min_fill_ratio {
+            if trade_request.order_type != OrderType::Kill {
+                return Err(anyhow!("Min fill ratio only applies to kill orders"));
+            }
+            if !(min_fill_ratio > 0.0 && min_fill_ratio <= 1.0) {
+                return Err(anyhow!("Min fill ratio must be in (0, 1]"));
+            }
+        }
+
         Ok(Order {
             id: trade_request.id,
             type_: trade_request.order_type,
@@ -55,11 +208,147 @@ impl TryFrom<TradeRequest> for Order {
             remaining_quantity: trade_request.quantity,
             minimum_quantity: trade_request.minimum_quantity,
             virtual_remaining_quantity: trade_request.quantity,
+            inserted_at: Utc::now().timestamp_millis(),
+            sequence: 0,
+            min_fill_ratio: trade_request.min_fill_ratio,
+            account_id: trade_request.account_id,
         })
     }
 }
 
 pub struct AppState {
     pub order_engine_sender: crossbeam::channel::Sender<OrderRequest>,
+    /// Channel for user cancels, which may be the order engine
+    /// channel or a separate higher priority one
+    pub cancel_request_sender: crossbeam::channel::Sender<OrderRequest>,
     pub order_expiration_sender: crossbeam::channel::Sender<ExpirationOrderRequest>,
+    pub max_order_age: Duration,
+    /// Key required in the x-admin-key header by admin endpoints,
+    /// which are disabled when None
+    pub admin_key: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{FixedOffset, TimeZone};
+
+    fn trade_request(sent_at: Option<NaiveDateTime>) -> TradeRequest {
+        TradeRequest {
+            sent_at,
+            ..TradeRequest::normal(OrderSide::Buy, 1, 1)
+        }
+    }
+
+    #[test]
+    fn fresh_trade_request_not_stale() {
+        let now = Utc::now();
+        let request = trade_request(Some((now - Duration::seconds(1)).naive_utc()));
+
+        assert!(!request.is_stale(Duration::seconds(5), now));
+    }
+
+    #[test]
+    fn old_trade_request_stale() {
+        let now = Utc::now();
+        let request = trade_request(Some((now - Duration::seconds(10)).naive_utc()));
+
+        assert!(request.is_stale(Duration::seconds(5), now));
+    }
+
+    #[test]
+    fn expiration_with_offset_converts_to_unix_timestamp() {
+        let expiration_date = FixedOffset::east_opt(2 * 3600)
+            .unwrap()
+            .with_ymd_and_hms(2025, 1, 1, 12, 0, 0)
+            .unwrap();
+        let request = TradeRequest {
+            expiration_date: Some(expiration_date.to_utc()),
+            ..trade_request(None)
+        };
+
+        // 12:00 at +02:00 is 10:00 UTC
+        assert_eq!(
+            request.expiration_timestamp_millis(),
+            Some(1_735_725_600_000)
+        );
+    }
+
+    fn deserialized_expiration_date(expiration_date: &str) -> Option<DateTime<Utc>> {
+        let mut request = serde_json::to_value(trade_request(None)).unwrap();
+        request["expiration_date"] = serde_json::Value::from(expiration_date);
+
+        serde_json::from_value::<TradeRequest>(request)
+            .unwrap()
+            .expiration_date
+    }
+
+    #[test]
+    fn rfc_3339_expiration_date_deserialized() {
+        let expiration_date = deserialized_expiration_date("2025-01-01T12:00:00+02:00");
+
+        assert_eq!(
+            expiration_date,
+            Some(Utc.with_ymd_and_hms(2025, 1, 1, 10, 0, 0).unwrap())
+        );
+    }
+
+    #[test]
+    fn naive_expiration_date_deserialized_as_utc() {
+        let expiration_date = deserialized_expiration_date("2025-01-01T12:00:00");
+
+        assert_eq!(
+            expiration_date,
+            Some(Utc.with_ymd_and_hms(2025, 1, 1, 12, 0, 0).unwrap())
+        );
+    }
+
+    #[test]
+    fn missing_expiration_date_deserialized_as_none() {
+        let mut request = serde_json::to_value(trade_request(None)).unwrap();
+        request.as_object_mut().unwrap().remove("expiration_date");
+
+        let request: TradeRequest = serde_json::from_value(request).unwrap();
+        assert_eq!(request.expiration_date, None);
+    }
+
+    #[test]
+    fn min_fill_ratio_rejected_on_non_kill_order() {
+        let request = TradeRequest {
+            min_fill_ratio: Some(0.5),
+            ..trade_request(None)
+        };
+
+        let error = Order::try_from(request).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Min fill ratio only applies to kill orders"
+        );
+    }
+
+    #[test]
+    fn nil_order_id_rejected() {
+        let request = TradeRequest {
+            id: Uuid::nil(),
+            ..trade_request(None)
+        };
+
+        let error = Order::try_from(request).unwrap_err();
+        assert_eq!(error.to_string(), "Order id cannot be nil");
+    }
+
+    #[test]
+    fn v4_order_id_accepted() {
+        let request = trade_request(None);
+        let order_id = request.id;
+
+        assert_eq!(Order::try_from(request).unwrap().id, order_id);
+    }
+
+    #[test]
+    fn trade_request_without_sent_at_not_stale() {
+        let request = trade_request(None);
+
+        assert!(!request.is_stale(Duration::seconds(5), Utc::now()));
+    }
 }